@@ -0,0 +1,88 @@
+//! Throughput of turning chat into terminal lines & log lines
+//!
+//! Run with `cargo bench`, the fixture is a recorded channel without PINGs.
+use chrono::Utc;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::io::{self, Write};
+use twitch_irc::message::{IRCMessage, ServerMessage};
+
+use twitch_ircv::{log_v0, message_handler, parse_badges, Formatter, RoomState};
+
+fn fixture() -> Vec<ServerMessage> {
+    include_str!("../tests/irc_data_no_ping")
+        .lines()
+        .map(|line| ServerMessage::try_from(IRCMessage::parse(line).unwrap()).unwrap())
+        .collect()
+}
+
+fn privmsgs(messages: &[ServerMessage]) -> Vec<twitch_irc::message::PrivmsgMessage> {
+    messages
+        .iter()
+        .filter_map(|message| match message {
+            ServerMessage::Privmsg(msg) => Some(msg.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn formatting(c: &mut Criterion) {
+    let messages = fixture();
+    let chat = privmsgs(&messages);
+    let start_time = Utc::now();
+
+    let mut group = c.benchmark_group("formatting");
+    group.throughput(Throughput::Elements(chat.len() as u64));
+    group.bench_function("parse_badges", |b| {
+        b.iter(|| {
+            for msg in &chat {
+                criterion::black_box(parse_badges(&msg.badges));
+            }
+        })
+    });
+    let formatter = Formatter::new(start_time);
+    group.bench_function("chat_message", |b| {
+        b.iter(|| {
+            for msg in &chat {
+                criterion::black_box(formatter.chat_message(msg));
+            }
+        })
+    });
+    // The same lines, written out without building a string for each
+    group.bench_function("chat_line", |b| {
+        b.iter(|| {
+            for msg in &chat {
+                writeln!(io::sink(), "{}", formatter.chat_line(msg)).unwrap();
+            }
+        })
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("pipeline");
+    group.throughput(Throughput::Elements(messages.len() as u64));
+    group.bench_function("message_handler", |b| {
+        b.iter(|| {
+            let mut room_state = RoomState::default();
+            for message in messages.iter().cloned() {
+                message_handler(
+                    message,
+                    start_time,
+                    start_time,
+                    &mut room_state,
+                    &mut io::sink(),
+                )
+                .unwrap();
+            }
+        })
+    });
+    group.bench_function("log_v0", |b| {
+        b.iter(|| {
+            for message in messages.iter().cloned() {
+                log_v0(message, &mut io::sink()).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, formatting);
+criterion_main!(benches);
@@ -1,13 +1,35 @@
+//! Prints the badges of every chatter next to how the viewer shows them
+use async_trait::async_trait;
 use twitch_irc::message::ServerMessage;
+use twitch_ircv::{Formatter, Policy, Sink, Source, Viewer};
 
-mod common;
+struct Badges(Formatter);
 
-#[tokio::main]
-async fn main() {
-    common::for_each_message(|message| {
+#[async_trait]
+impl Sink for Badges {
+    async fn send(&mut self, message: ServerMessage) -> twitch_ircv::Result<bool> {
         if let ServerMessage::Privmsg(message) = message {
-            println!("{} {:?}", message.sender.name, message.badges);
+            println!("{:?}", message.badges);
+            println!("{}", self.0.chat_message(&message));
         }
-    })
-    .await
+        Ok(true)
+    }
+
+    async fn finish(&mut self) -> twitch_ircv::Result<u64> {
+        Ok(0)
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let channel = std::env::args()
+        .nth(1)
+        .expect("Must have channel name as first arg");
+    Viewer::builder(Source::Channel(channel))
+        .filter(|message| matches!(message, ServerMessage::Privmsg(_)))
+        .sink(Badges(Formatter::new(chrono::Utc::now())), Policy::Block)
+        .build()
+        .run()
+        .await
+        .expect("Unable to join the channel");
 }
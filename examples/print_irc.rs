@@ -1,11 +1,31 @@
-use twitch_irc::message::AsRawIRC;
+//! Prints the raw irc of a channel, e.g. `cargo run --example print_irc bread`
+use async_trait::async_trait;
+use twitch_irc::message::{AsRawIRC, ServerMessage};
+use twitch_ircv::{Policy, Sink, Source, Viewer};
 
-mod common;
+struct RawIrc;
+
+#[async_trait]
+impl Sink for RawIrc {
+    async fn send(&mut self, message: ServerMessage) -> twitch_ircv::Result<bool> {
+        println!("{}", message.as_raw_irc());
+        Ok(true)
+    }
+
+    async fn finish(&mut self) -> twitch_ircv::Result<u64> {
+        Ok(0)
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    common::for_each_message(|message| {
-        println!("{}", message.as_raw_irc());
-    })
-    .await
+    let channel = std::env::args()
+        .nth(1)
+        .expect("Must have channel name as first arg");
+    Viewer::builder(Source::Channel(channel))
+        .sink(RawIrc, Policy::Block)
+        .build()
+        .run()
+        .await
+        .expect("Unable to join the channel");
 }
@@ -0,0 +1,157 @@
+//! Chat activity at a glance, for a status line
+use chrono::{DateTime, Utc};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+use crate::room_state::short_duration;
+
+/// Minutes the sparkline covers, one character each.
+pub const SPARKLINE_MINUTES: usize = 30;
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Counts recent messages & their senders.
+#[derive(Clone, Debug)]
+pub struct Activity {
+    /// Unique chatters are counted over this.
+    window: Duration,
+    /// Messages sent each minute, by minutes since the epoch, oldest first.
+    per_minute: VecDeque<(i64, u64)>,
+    /// Senders of the messages in the window, or the last minute if longer.
+    recent: VecDeque<(DateTime<Utc>, String)>,
+}
+
+/// What [Activity::status] reports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Status {
+    /// Messages in the last minute.
+    pub per_minute: u64,
+    /// Different senders in the window.
+    pub chatters: usize,
+    pub window: Duration,
+    /// Messages each minute, for the last [SPARKLINE_MINUTES].
+    pub sparkline: String,
+}
+
+impl Activity {
+    /// Unique chatters are counted over the last `window`.
+    pub fn new(window: Duration) -> Activity {
+        Activity {
+            window,
+            per_minute: VecDeque::new(),
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// `login` sent a message at `time`.
+    pub fn record(&mut self, time: DateTime<Utc>, login: &str) {
+        let minute = time.timestamp().div_euclid(60);
+        match self.per_minute.back_mut() {
+            Some((last, count)) if *last == minute => *count += 1,
+            _ => self.per_minute.push_back((minute, 1)),
+        }
+        self.recent.push_back((time, login.to_string()));
+    }
+
+    /// The activity up to `now`, forgetting what's too old to matter.
+    pub fn status(&mut self, now: DateTime<Utc>) -> Status {
+        let kept = self.window.max(Duration::from_secs(60));
+        let since = before(now, kept);
+        while self.recent.front().is_some_and(|(time, _)| *time < since) {
+            self.recent.pop_front();
+        }
+        let minute = now.timestamp().div_euclid(60);
+        let first_minute = minute - SPARKLINE_MINUTES as i64 + 1;
+        while self
+            .per_minute
+            .front()
+            .is_some_and(|(m, _)| *m < first_minute)
+        {
+            self.per_minute.pop_front();
+        }
+
+        let last_minute = now - chrono::Duration::seconds(60);
+        let window = before(now, self.window);
+        let per_minute = self.recent.iter().filter(|(time, _)| *time > last_minute);
+        let chatters = self.recent.iter().filter(|(time, _)| *time >= window);
+        let chatters: HashSet<_> = chatters.map(|(_, login)| login).collect();
+        Status {
+            per_minute: per_minute.count() as u64,
+            chatters: chatters.len(),
+            window: self.window,
+            sparkline: self.sparkline(first_minute),
+        }
+    }
+
+    fn sparkline(&self, first_minute: i64) -> String {
+        let mut counts = vec![0; SPARKLINE_MINUTES];
+        for (minute, count) in &self.per_minute {
+            if let Some(slot) = counts.get_mut((minute - first_minute) as usize) {
+                *slot = *count;
+            }
+        }
+        let max = counts.iter().copied().max().unwrap_or(0).max(1);
+        let last = SPARKS.len() as u64 - 1;
+        counts
+            .iter()
+            .map(|count| SPARKS[(count * last).div_ceil(max) as usize])
+            .collect()
+    }
+}
+
+/// `duration` before `now`, or the dawn of time for absurd durations.
+fn before(now: DateTime<Utc>, duration: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(duration)
+        .ok()
+        .and_then(|duration| now.checked_sub_signed(duration))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+/// `▁▁▃▇█ 42 msg/min, 17 chatters in 5m`
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} msg/min, {} chatters in {}",
+            self.sparkline,
+            self.per_minute,
+            self.chatters,
+            short_duration(&self.window)
+        )
+    }
+}
+
+#[test]
+fn activity_is_counted_per_minute() {
+    let start = DateTime::parse_from_rfc3339("2024-04-21T19:00:00Z")
+        .unwrap()
+        .with_timezone(&Utc);
+    let at = |secs| start + chrono::Duration::seconds(secs);
+    let mut activity = Activity::new(Duration::from_secs(300));
+    // 4 messages 10 minutes ago, then 2 in the last minute
+    for login in ["crumb", "crumb", "toast", "bread"] {
+        activity.record(at(0), login);
+    }
+    activity.record(at(590), "crumb");
+    activity.record(at(599), "crumb");
+
+    let status = activity.status(at(600));
+    assert_eq!(status.per_minute, 2);
+    assert_eq!(status.chatters, 1);
+    assert_eq!(status.sparkline.chars().count(), SPARKLINE_MINUTES);
+    assert_eq!(
+        status.to_string(),
+        format!(
+            "{}█{}▅▁ 2 msg/min, 1 chatters in 5m",
+            "▁".repeat(19),
+            "▁".repeat(8)
+        )
+    );
+
+    // Long after, everything is forgotten
+    let status = activity.status(at(3600));
+    assert_eq!(status.per_minute, 0);
+    assert_eq!(status.sparkline, "▁".repeat(SPARKLINE_MINUTES));
+    assert!(activity.recent.is_empty() && activity.per_minute.is_empty());
+}
@@ -0,0 +1,618 @@
+//! Chat archived in SQLite, for querying more than grepping allows
+//!
+//! Messages, their senders, their badges & chat events each get a table.
+//! Only built with the `sqlite` feature.
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::types::ToSql;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use twitch_irc::message::{ClearChatAction, ServerMessage};
+
+use crate::args::{ImportArgs, QueryArgs};
+use crate::error::{Error, Result};
+use crate::export::TimeBound;
+use crate::overlay::hex_color;
+use crate::room_state::{short_duration, RoomState};
+use crate::setup::filein_to_smsg;
+use crate::sink::Sink;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS users (
+    id TEXT PRIMARY KEY,
+    login TEXT NOT NULL,
+    name TEXT NOT NULL,
+    color TEXT
+);
+CREATE TABLE IF NOT EXISTS messages (
+    id TEXT PRIMARY KEY,
+    channel TEXT NOT NULL,
+    user_id TEXT NOT NULL REFERENCES users (id),
+    sent_at INTEGER NOT NULL,
+    text TEXT NOT NULL,
+    action INTEGER NOT NULL,
+    removed INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS messages_by_time ON messages (channel, sent_at);
+CREATE INDEX IF NOT EXISTS messages_by_user ON messages (user_id, sent_at);
+CREATE TABLE IF NOT EXISTS badges (
+    message_id TEXT NOT NULL REFERENCES messages (id),
+    name TEXT NOT NULL,
+    version TEXT NOT NULL,
+    PRIMARY KEY (message_id, name)
+);
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY,
+    channel TEXT NOT NULL,
+    -- ROOMSTATE has no timestamp, chat mode changes have the one of the
+    -- message before them, NULL if there was none
+    sent_at INTEGER,
+    kind TEXT NOT NULL,
+    user_login TEXT,
+    text TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS events_by_time ON events (channel, sent_at);
+";
+
+/// Makes importing events twice a no-op, after dropping the duplicates
+/// archives from before it may hold.
+const EVENTS_KEY: &str = "
+DELETE FROM events WHERE id NOT IN (
+    SELECT min(id) FROM events
+    GROUP BY channel, ifnull(sent_at, -1), kind, ifnull(user_login, ''), text
+);
+CREATE UNIQUE INDEX events_key
+ON events (channel, ifnull(sent_at, -1), kind, ifnull(user_login, ''), text);
+";
+
+/// A SQLite database of chat.
+///
+/// Times are stored as milliseconds since the epoch, like `tmi-sent-ts`.
+pub struct Archive {
+    path: PathBuf,
+    conn: Connection,
+    room_state: RoomState,
+    /// When the last message with a timestamp was sent, for the ones
+    /// without.
+    last_sent_at: Option<i64>,
+    /// Inside a transaction not yet committed.
+    pending: bool,
+}
+
+impl Archive {
+    /// Opens the archive at `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Archive> {
+        let error = archive_error(path);
+        let conn = Connection::open(path).map_err(&error)?;
+        conn.execute_batch(SCHEMA).map_err(&error)?;
+        let keyed: bool = conn
+            .query_row(
+                "SELECT count(*) FROM sqlite_master WHERE name = 'events_key'",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(&error)?;
+        if !keyed {
+            conn.execute_batch(EVENTS_KEY).map_err(&error)?;
+        }
+        Ok(Archive {
+            path: path.to_path_buf(),
+            conn,
+            room_state: RoomState::default(),
+            last_sent_at: None,
+            pending: false,
+        })
+    }
+
+    /// Archives `message`, if it's one [log_v0](crate::logging::log_v0)
+    /// would log.
+    ///
+    /// Messages & events already in the archive are skipped, so a log can
+    /// be imported twice. Returns whether `message` was a chat message new
+    /// to the archive.
+    ///
+    /// Bans, timeouts & clears are only archived as events, a message is
+    /// only marked removed by a CLEARMSG for it.
+    pub fn insert(&mut self, message: &ServerMessage) -> Result<bool> {
+        if !self.pending {
+            self.sql(|conn| conn.execute_batch("BEGIN"))?;
+            self.pending = true;
+        }
+        match message {
+            ServerMessage::Privmsg(msg) => {
+                let color = msg.name_color.map(hex_color);
+                let sent_at = msg.server_timestamp.timestamp_millis();
+                self.last_sent_at = Some(sent_at);
+                let inserted = self.sql(|conn| {
+                    conn.execute(
+                        "INSERT INTO users (id, login, name, color) VALUES (?1, ?2, ?3, ?4)
+                         ON CONFLICT (id) DO UPDATE
+                         SET login = ?2, name = ?3, color = ?4",
+                        params![msg.sender.id, msg.sender.login, msg.sender.name, color],
+                    )?;
+                    let inserted = conn.execute(
+                        "INSERT OR IGNORE INTO messages
+                         (id, channel, user_id, sent_at, text, action)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                        params![
+                            msg.message_id,
+                            msg.channel_login,
+                            msg.sender.id,
+                            sent_at,
+                            msg.message_text,
+                            msg.is_action
+                        ],
+                    )?;
+                    if inserted > 0 {
+                        for badge in &msg.badges {
+                            conn.execute(
+                                "INSERT OR IGNORE INTO badges (message_id, name, version)
+                                 VALUES (?1, ?2, ?3)",
+                                params![msg.message_id, badge.name, badge.version],
+                            )?;
+                        }
+                    }
+                    Ok(inserted > 0)
+                })?;
+                return Ok(inserted);
+            }
+            ServerMessage::ClearMsg(msg) => {
+                self.last_sent_at = Some(msg.server_timestamp.timestamp_millis());
+                self.sql(|conn| {
+                    conn.execute(
+                        "UPDATE messages SET removed = 1 WHERE id = ?1",
+                        params![msg.message_id],
+                    )
+                    .map(drop)
+                })?
+            }
+            ServerMessage::ClearChat(msg) => {
+                let sent_at = msg.server_timestamp.timestamp_millis();
+                self.last_sent_at = Some(sent_at);
+                let (kind, user, text) = match &msg.action {
+                    ClearChatAction::ChatCleared => ("clear", None, "Chat was cleared".to_string()),
+                    ClearChatAction::UserBanned { user_login, .. } => {
+                        ("ban", Some(user_login), format!("{user_login} was banned"))
+                    }
+                    ClearChatAction::UserTimedOut {
+                        user_login,
+                        timeout_length,
+                        ..
+                    } => (
+                        "timeout",
+                        Some(user_login),
+                        format!(
+                            "{user_login} was timed out for {}",
+                            short_duration(timeout_length)
+                        ),
+                    ),
+                };
+                self.sql(|conn| {
+                    insert_event(conn, &msg.channel_login, Some(sent_at), kind, user, &text)
+                })?;
+            }
+            ServerMessage::UserNotice(msg) => {
+                let text = match &msg.message_text {
+                    Some(text) => format!("{}: {text}", msg.system_message),
+                    None => msg.system_message.clone(),
+                };
+                let sent_at = Some(msg.server_timestamp.timestamp_millis());
+                self.last_sent_at = sent_at;
+                self.sql(|conn| {
+                    let (channel, user) = (&msg.channel_login, Some(&msg.sender.login));
+                    insert_event(conn, channel, sent_at, &msg.event_id, user, &text)
+                })?;
+            }
+            ServerMessage::RoomState(msg) => {
+                let sent_at = self.last_sent_at;
+                for change in self.room_state.update(msg) {
+                    let text = change.to_string();
+                    let channel = &msg.channel_login;
+                    self.sql(|conn| insert_event(conn, channel, sent_at, "mode", None, &text))?;
+                }
+            }
+            _ => (),
+        }
+        Ok(false)
+    }
+
+    /// Makes what was inserted so far permanent.
+    pub fn commit(&mut self) -> Result<()> {
+        if self.pending {
+            self.sql(|conn| conn.execute_batch("COMMIT"))?;
+            self.pending = false;
+        }
+        Ok(())
+    }
+
+    /// Chat messages matching `query`, oldest first.
+    pub fn messages(&self, query: &Query) -> Result<Vec<ArchivedMessage>> {
+        let mut sql = "SELECT messages.sent_at, messages.channel, users.login, users.name,
+                       messages.text, messages.action, messages.removed
+                       FROM messages JOIN users ON users.id = messages.user_id
+                       WHERE 1"
+            .to_string();
+        let mut params: Vec<Box<dyn ToSql>> = vec![];
+        let start = self.first_sent_at(query.channel.as_deref())?;
+        let mut bound = |clause: &str, value: Box<dyn ToSql>| {
+            sql.push_str(clause);
+            params.push(value);
+        };
+        if let Some(channel) = &query.channel {
+            bound(" AND messages.channel = ?", Box::new(channel.clone()));
+        }
+        if let Some(user) = &query.user {
+            bound(
+                " AND users.login = ? COLLATE NOCASE",
+                Box::new(user.clone()),
+            );
+        }
+        if let Some(from) = query.from {
+            let from = from.resolve(start).timestamp_millis();
+            bound(" AND messages.sent_at >= ?", Box::new(from));
+        }
+        if let Some(to) = query.to {
+            let to = to.resolve(start).timestamp_millis();
+            bound(" AND messages.sent_at <= ?", Box::new(to));
+        }
+        if let Some(text) = &query.text {
+            let pattern = format!("%{}%", escape_like(text));
+            bound(" AND messages.text LIKE ? ESCAPE '\\'", Box::new(pattern));
+        }
+        // A negative limit is none
+        let limit = query.limit.map_or(-1, i64::from);
+        bound(
+            " ORDER BY messages.sent_at, messages.rowid LIMIT ?",
+            Box::new(limit),
+        );
+        self.sql(|conn| {
+            let mut statement = conn.prepare(&sql)?;
+            let params: Vec<&dyn ToSql> = params.iter().map(|p| p.as_ref()).collect();
+            let rows = statement.query_map(params.as_slice(), |row| {
+                Ok(ArchivedMessage {
+                    sent_at: from_millis(row.get(0)?),
+                    channel: row.get(1)?,
+                    login: row.get(2)?,
+                    name: row.get(3)?,
+                    text: row.get(4)?,
+                    action: row.get(5)?,
+                    removed: row.get(6)?,
+                })
+            })?;
+            rows.collect()
+        })
+    }
+
+    /// When the archive, or its part in `channel`, starts. `HH:MM` times
+    /// in a [Query] are relative to it.
+    fn first_sent_at(&self, channel: Option<&str>) -> Result<DateTime<Utc>> {
+        let first: Option<i64> = self.sql(|conn| {
+            conn.query_row(
+                "SELECT min(sent_at) FROM messages WHERE ?1 IS NULL OR channel = ?1",
+                params![channel],
+                |row| row.get(0),
+            )
+            .optional()
+            .map(Option::flatten)
+        })?;
+        Ok(first.map(from_millis).unwrap_or_default())
+    }
+
+    fn sql<T, F>(&self, run: F) -> Result<T>
+    where
+        F: FnOnce(&Connection) -> rusqlite::Result<T>,
+    {
+        run(&self.conn).map_err(archive_error(&self.path))
+    }
+}
+
+fn insert_event(
+    conn: &Connection,
+    channel: &str,
+    sent_at: Option<i64>,
+    kind: &str,
+    user: Option<&String>,
+    text: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO events (channel, sent_at, kind, user_login, text)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![channel, sent_at, kind, user, text],
+    )
+    .map(drop)
+}
+
+fn archive_error(path: &Path) -> impl Fn(rusqlite::Error) -> Error + '_ {
+    move |source| Error::Archive {
+        path: path.to_path_buf(),
+        source: Box::new(source),
+    }
+}
+
+fn from_millis(millis: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .unwrap_or_default()
+}
+
+/// Escapes `%`, `_` & `\` for a `LIKE … ESCAPE '\'` pattern.
+fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Which messages [Archive::messages] returns, everything by default.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query {
+    pub channel: Option<String>,
+    /// Login, case-insensitive.
+    pub user: Option<String>,
+    pub from: Option<TimeBound>,
+    pub to: Option<TimeBound>,
+    /// Text the message contains, case-insensitive for ASCII.
+    pub text: Option<String>,
+    pub limit: Option<u32>,
+}
+
+/// A chat message as the archive has it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchivedMessage {
+    pub sent_at: DateTime<Utc>,
+    pub channel: String,
+    pub login: String,
+    pub name: String,
+    pub text: String,
+    pub action: bool,
+    /// Deleted by a moderator.
+    pub removed: bool,
+}
+
+impl std::fmt::Display for ArchivedMessage {
+    /// `YYYY-MM-DD HH:MM:SS #channel name: text`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let separator = if self.action { " " } else { ": " };
+        write!(
+            f,
+            "{} #{} {}{separator}{}",
+            self.sent_at.format("%Y-%m-%d %H:%M:%S"),
+            self.channel,
+            self.name,
+            self.text
+        )?;
+        if self.removed {
+            write!(f, " [removed]")?;
+        }
+        Ok(())
+    }
+}
+
+/// Archives every message, committing about once a second.
+pub struct ArchiveSink {
+    archive: Archive,
+    archived: u64,
+}
+
+impl ArchiveSink {
+    pub fn new(archive: Archive) -> ArchiveSink {
+        ArchiveSink {
+            archive,
+            archived: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for ArchiveSink {
+    async fn send(&mut self, message: ServerMessage) -> Result<bool> {
+        if self.archive.insert(&message)? {
+            self.archived += 1;
+        }
+        Ok(true)
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(1))
+    }
+
+    async fn tick(&mut self) -> Result<()> {
+        self.archive.commit()
+    }
+
+    /// Returns the number of chat messages archived.
+    async fn finish(&mut self) -> Result<u64> {
+        self.archive.commit()?;
+        Ok(self.archived)
+    }
+}
+
+/// Imports the log of `args` into its archive.
+pub fn import(args: ImportArgs) -> Result<()> {
+    let log = File::open(&args.log_file).map_err(|source| Error::LogOpen {
+        path: args.log_file.clone(),
+        source,
+    })?;
+    let mut archive = Archive::open(&args.archive)?;
+    let mut imported = 0;
+    for message in filein_to_smsg(io::BufReader::new(log)) {
+        if archive.insert(&message?)? {
+            imported += 1;
+        }
+    }
+    archive.commit()?;
+    println!(
+        "Imported {imported} messages into {}",
+        args.archive.display()
+    );
+    Ok(())
+}
+
+/// Prints the messages `args` asks for, one per line.
+pub fn query(args: QueryArgs) -> Result<()> {
+    let archive = Archive::open(&args.archive)?;
+    let messages = archive.messages(&Query {
+        channel: args.channel,
+        user: args.user,
+        from: args.from,
+        to: args.to,
+        text: args.text,
+        limit: args.limit,
+    })?;
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    for message in messages {
+        writeln!(out, "{message}").map_err(Error::Output)?;
+    }
+    out.flush().map_err(Error::Output)
+}
+
+#[cfg(test)]
+fn privmsg(id: u32, login: &str, ts: u64, text: &str) -> ServerMessage {
+    let raw = format!(
+        "@badge-info=;badges=moderator/1,partner/1;color=#FF0000;display-name={login};emotes=;\
+         flags=;id={id};mod=1;room-id=1;subscriber=0;tmi-sent-ts={ts};turbo=0;\
+         user-id={login}-id;user-type= :{login}!{login}@{login}.tmi.twitch.tv PRIVMSG #bread :{text}"
+    );
+    let irc = twitch_irc::message::IRCMessage::parse(&raw).unwrap();
+    ServerMessage::try_from(irc).unwrap()
+}
+
+#[test]
+fn archives_and_queries_messages() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("chat.db");
+    let mut archive = Archive::open(&path).unwrap();
+    let messages = [
+        privmsg(1, "crumb", 1676000000000, "hello 100%"),
+        privmsg(2, "bread", 1676000060000, "hi crumb"),
+        privmsg(3, "crumb", 1676000120000, "spam"),
+    ];
+    for message in &messages {
+        assert!(archive.insert(message).unwrap());
+    }
+    let parse = |raw: &str| {
+        let irc = twitch_irc::message::IRCMessage::parse(raw).unwrap();
+        ServerMessage::try_from(irc).unwrap()
+    };
+    let events = [
+        parse(
+            "@login=crumb;room-id=1;target-msg-id=1;tmi-sent-ts=1676000125000 \
+             :tmi.twitch.tv CLEARMSG #bread :hello 100%",
+        ),
+        parse(
+            "@room-id=1;target-user-id=crumb-id;tmi-sent-ts=1676000130000 \
+             :tmi.twitch.tv CLEARCHAT #bread :crumb",
+        ),
+    ];
+    for event in &events {
+        assert!(!archive.insert(event).unwrap());
+    }
+    let counts = |archive: &Archive| {
+        archive
+            .sql(|conn| {
+                let count = |table| {
+                    let sql = format!("SELECT count(*) FROM {table}");
+                    conn.query_row(&sql, [], |r| r.get::<_, u32>(0))
+                };
+                Ok([count("messages")?, count("badges")?, count("events")?])
+            })
+            .unwrap()
+    };
+    assert_eq!(counts(&archive), [3, 6, 1]);
+    // Importing the same messages again changes nothing
+    for message in &messages {
+        assert!(!archive.insert(message).unwrap());
+    }
+    for event in &events {
+        assert!(!archive.insert(event).unwrap());
+    }
+    assert_eq!(counts(&archive), [3, 6, 1]);
+    archive.commit().unwrap();
+    drop(archive);
+
+    let archive = Archive::open(&path).unwrap();
+    let texts = |query: Query| {
+        let messages = archive.messages(&query).unwrap();
+        messages.into_iter().map(|m| m.text).collect::<Vec<_>>()
+    };
+    assert_eq!(texts(Query::default()), ["hello 100%", "hi crumb", "spam"]);
+    let user = Some("CRUMB".to_string());
+    assert_eq!(
+        texts(Query {
+            user,
+            ..Query::default()
+        }),
+        ["hello 100%", "spam"]
+    );
+    assert_eq!(
+        texts(Query {
+            from: "00:01".parse().ok(),
+            to: "2023-02-10T03:34:20Z".parse().ok(),
+            ..Query::default()
+        }),
+        ["hi crumb"]
+    );
+    assert_eq!(
+        texts(Query {
+            text: Some("0%".to_string()),
+            ..Query::default()
+        }),
+        ["hello 100%"]
+    );
+    assert!(texts(Query {
+        text: Some("_".to_string()),
+        ..Query::default()
+    })
+    .is_empty());
+    assert_eq!(
+        texts(Query {
+            limit: Some(1),
+            ..Query::default()
+        }),
+        ["hello 100%"]
+    );
+
+    let messages = archive.messages(&Query::default()).unwrap();
+    assert_eq!(
+        messages[0].to_string(),
+        "2023-02-10 03:33:20 #bread crumb: hello 100% [removed]"
+    );
+    // The ban is only an event, it doesn't remove what crumb said before
+    assert!(!messages[2].removed);
+    let event: String = archive
+        .sql(|conn| conn.query_row("SELECT kind || ' ' || text FROM events", [], |r| r.get(0)))
+        .unwrap();
+    assert_eq!(event, "ban crumb was banned");
+}
+
+#[test]
+fn the_sample_log_imports() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive_path = dir.path().join("chat.db");
+    let import_sample = || {
+        import(ImportArgs {
+            log_file: "tests/irc_data_no_ping".into(),
+            archive: archive_path.clone(),
+        })
+        .unwrap();
+        let archive = Archive::open(&archive_path).unwrap();
+        let events: u32 = archive
+            .sql(|conn| conn.query_row("SELECT count(*) FROM events", [], |r| r.get(0)))
+            .unwrap();
+        (archive.messages(&Query::default()).unwrap(), events)
+    };
+    let (messages, events) = import_sample();
+    assert!(events > 0);
+    // A second import adds nothing, mode changes included
+    assert_eq!(import_sample(), (messages.clone(), events));
+    assert!(messages.len() > 10);
+    assert!(messages.iter().all(|m| m.channel == "harukakaribu"));
+    assert!(messages.windows(2).all(|w| w[0].sent_at <= w[1].sent_at));
+}
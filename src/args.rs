@@ -1,5 +1,19 @@
 use argh::FromArgs;
-use std::path::PathBuf;
+use regex::Regex;
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::dedup::Dedup;
+use crate::endpoint::{Endpoint, Pool, Transport};
+use crate::export::{EntryKind, ExportFormat, TimeBound};
+use crate::queue::Policy;
+
+/// Messages each queue between the input & the outputs holds.
+pub const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+/// How long to wait for twitch to confirm joining a channel.
+pub const DEFAULT_JOIN_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Pretty print the live chat of a twitch channel.
 /// Also offers support for logging (most) of the irc messages posted in chat.
@@ -7,9 +21,22 @@ use std::path::PathBuf;
 /// Note:
 /// * Color can be prohibited with the enviromental variable NO_COLOR.
 /// * Color can be forced using the enviromental variable CLICOLOR_FORCE.
-#[derive(FromArgs, Default)]
+#[derive(FromArgs)]
+#[argh(
+    error_code(1, "the arguments are invalid."),
+    error_code(2, "twitch refused the connection, e.g. a suspended channel."),
+    error_code(3, "joining the channel timed out."),
+    error_code(4, "the channel name is invalid."),
+    error_code(5, "the log file couldn't be opened."),
+    error_code(6, "writing the log or the chat output failed."),
+    error_code(7, "the input couldn't be read or isn't irc."),
+    error_code(8, "--login was given without usable credentials."),
+    error_code(9, "serve or --overlay couldn't listen on its address."),
+    error_code(10, "the sqlite archive couldn't be used.")
+)]
 pub struct Args {
-    #[argh(positional)]
+    /// the channel to view, not needed with --from-stdin or a command.
+    #[argh(positional, default = "String::new()")]
     pub channel_name: String,
 
     /// file to write irc log to.
@@ -21,8 +48,419 @@ pub struct Args {
     pub append: bool,
 
     /// don't connect to a twitch irc channel, read raw irc from stdin.
-    /// Note that you are still required to include a channel_name, it will be
-    /// ignored.
     #[argh(switch)]
     pub from_stdin: bool,
+
+    /// seconds between flushes of the log file, 0 flushes every message
+    /// (default 1).
+    #[argh(option, default = "1")]
+    pub flush_interval: u64,
+
+    /// seconds to wait for twitch to confirm joining the channel, 0 waits
+    /// forever (default 10).
+    #[argh(option, default = "DEFAULT_JOIN_TIMEOUT.as_secs()")]
+    pub join_timeout: u64,
+
+    /// messages to hold for a slow terminal or disk before applying the
+    /// display policy (default 1024).
+    #[argh(
+        option,
+        default = "DEFAULT_BUFFER_SIZE",
+        from_str_fn(parse_buffer_size)
+    )]
+    pub buffer_size: usize,
+
+    /// what to do when the terminal can't keep up, `block` or `drop-oldest`
+    /// (default drop-oldest). The log never drops messages.
+    #[argh(option, default = "Policy::DropOldest")]
+    pub display_policy: Policy,
+
+    /// collapse runs of similar messages, like copypasta, into one line
+    /// with a counter. Only in a terminal, the log keeps every message.
+    #[argh(switch)]
+    pub dedup: bool,
+
+    /// seconds between two messages of a collapsed run (default 10).
+    #[argh(option, default = "10")]
+    pub dedup_window: u64,
+
+    /// how alike messages must be to collapse, from 0 to 1, where 1 only
+    /// ignores case & spacing (default 0.9).
+    #[argh(option, default = "0.9", from_str_fn(parse_similarity))]
+    pub dedup_similarity: f64,
+
+    /// don't wrap long messages. They're only wrapped in a terminal,
+    /// under the start of the message.
+    #[argh(switch)]
+    pub no_wrap: bool,
+
+    /// every this many seconds, show a status line with the messages in the
+    /// last minute, the chatters in the last --activity-window minutes & a
+    /// sparkline of the last 30 minutes.
+    #[argh(option)]
+    pub activity: Option<NonZeroU64>,
+
+    /// minutes to count chatters over for --activity (default 5).
+    #[argh(option, default = "5")]
+    pub activity_window: u64,
+
+    /// log in to send messages, typed lines are sent to the channel.
+    /// The login & token are read from TWITCH_IRCV_LOGIN & TWITCH_IRCV_TOKEN,
+    /// or else the credentials file.
+    #[argh(switch)]
+    pub login: bool,
+
+    /// file holding `login = ...` & `token = ...` lines
+    /// (default ~/.config/twitch-ircv/credentials).
+    #[argh(option)]
+    pub credentials: Option<PathBuf>,
+
+    /// file recording the moderator commands sent with --login
+    /// (default ~/.local/share/twitch-ircv/audit.log).
+    #[argh(option)]
+    pub audit_log: Option<PathBuf>,
+
+    /// irc server to connect to (default twitch's server for the
+    /// transport).
+    #[argh(option)]
+    pub host: Option<String>,
+
+    /// port of the irc server (default 6667 for tcp, 6697 for tls, 80 for
+    /// ws & 443 for wss).
+    #[argh(option)]
+    pub port: Option<u16>,
+
+    /// how to reach the irc server, `tcp`, `tls`, `ws` (WebSocket) or `wss`
+    /// (WebSocket over TLS) (default tls).
+    #[argh(option, default = "Transport::Tls")]
+    pub transport: Transport,
+
+    /// seconds to wait for a connection to open, including the TLS or
+    /// WebSocket handshake (default 20).
+    #[argh(option, default = "20")]
+    pub connect_timeout: u64,
+
+    /// seconds between opening connections, e.g. when reconnecting
+    /// (default 2).
+    #[argh(option, default = "2")]
+    pub connection_interval: u64,
+
+    /// messages sent but not yet acknowledged before another connection is
+    /// opened to send more (default 5).
+    #[argh(option, default = "5")]
+    pub max_waiting_messages: usize,
+
+    /// serve the chat as JSON over WebSocket on this address, e.g.
+    /// 127.0.0.1:6680, for overlays like overlay/chat.html.
+    #[argh(option)]
+    pub overlay: Option<String>,
+
+    /// also archive the chat in this SQLite database, see the query command.
+    #[cfg(feature = "sqlite")]
+    #[argh(option)]
+    pub archive: Option<PathBuf>,
+
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Things to do instead of viewing a channel.
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum Command {
+    Serve(ServeArgs),
+    Export(ExportArgs),
+    Search(SearchArgs),
+    User(UserArgs),
+    #[cfg(feature = "sqlite")]
+    Import(ImportArgs),
+    #[cfg(feature = "sqlite")]
+    Query(QueryArgs),
+}
+
+/// Serve a log written with -o as a twitch irc server, for other chat
+/// clients to connect to. Every client gets the whole log, in the channel
+/// it joined.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "serve")]
+pub struct ServeArgs {
+    /// the log to replay.
+    #[argh(positional)]
+    pub log_file: PathBuf,
+
+    /// address to listen on, for irc & irc over WebSocket
+    /// (default 127.0.0.1:6667).
+    #[argh(option, default = "DEFAULT_LISTEN.to_string()")]
+    pub listen: String,
+
+    /// keep the pauses between the messages, instead of sending all at once.
+    #[argh(switch)]
+    pub realtime: bool,
+}
+
+/// Export a log written with -o as a page or transcript to share. Messages
+/// removed by moderators are struck through.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "export")]
+pub struct ExportArgs {
+    /// the log to export.
+    #[argh(positional)]
+    pub log_file: PathBuf,
+
+    /// file to write the export to, stdout by default.
+    #[argh(option, short = 'o')]
+    pub output: Option<PathBuf>,
+
+    /// what to export as, `html`, `text` or `markdown` (default html).
+    #[argh(option, default = "ExportFormat::Html")]
+    pub format: ExportFormat,
+
+    /// start at this time, HH:MM[:SS] into the log or an RFC 3339 date.
+    #[argh(option)]
+    pub from: Option<TimeBound>,
+
+    /// end at this time, HH:MM[:SS] into the log or an RFC 3339 date.
+    #[argh(option)]
+    pub to: Option<TimeBound>,
+
+    /// only export what this user said or what happened to them, can be
+    /// repeated.
+    #[argh(option)]
+    pub user: Vec<String>,
+}
+
+/// Search logs written with -o, gzipped or not, & print the hits like the
+/// viewer shows chat. Every filter given must match.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "search")]
+pub struct SearchArgs {
+    /// the logs to search.
+    #[argh(positional)]
+    pub logs: Vec<PathBuf>,
+
+    /// regex the message or event text must match, e.g. `(?i)bread`.
+    #[argh(option, short = 'e')]
+    pub pattern: Option<Regex>,
+
+    /// only what this user said or what happened to them, can be repeated.
+    #[argh(option)]
+    pub user: Vec<String>,
+
+    /// start at this time, HH:MM[:SS] into each log or an RFC 3339 date.
+    #[argh(option)]
+    pub from: Option<TimeBound>,
+
+    /// end at this time, HH:MM[:SS] into each log or an RFC 3339 date.
+    #[argh(option)]
+    pub to: Option<TimeBound>,
+
+    /// only this kind of line, `chat`, `sub`, `raid`, `notice`, `ban`,
+    /// `timeout`, `clear` or `mode`, can be repeated.
+    #[argh(option, long = "type")]
+    pub kinds: Vec<EntryKind>,
+
+    /// lines to show before & after each hit (default 0).
+    #[argh(option, short = 'C', default = "0")]
+    pub context: usize,
+}
+
+/// Show everything logs written with -o say about one user: their
+/// messages, timeouts, bans & deleted messages, after a short profile.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "user")]
+pub struct UserArgs {
+    /// their login or user id.
+    #[argh(positional)]
+    pub user: String,
+
+    /// the logs to look in, gzipped or not.
+    #[argh(positional)]
+    pub logs: Vec<PathBuf>,
+}
+
+/// Import a log written with -o into a SQLite archive, messages already in
+/// it are skipped.
+#[cfg(feature = "sqlite")]
+#[derive(FromArgs)]
+#[argh(subcommand, name = "import")]
+pub struct ImportArgs {
+    /// the log to import.
+    #[argh(positional)]
+    pub log_file: PathBuf,
+
+    /// the archive to import into, created if needed.
+    #[argh(positional)]
+    pub archive: PathBuf,
+}
+
+/// Print the messages of a SQLite archive, oldest first. Every filter given
+/// must match.
+#[cfg(feature = "sqlite")]
+#[derive(FromArgs)]
+#[argh(subcommand, name = "query")]
+pub struct QueryArgs {
+    /// the archive, from --archive or the import command.
+    #[argh(positional)]
+    pub archive: PathBuf,
+
+    /// only messages in this channel.
+    #[argh(option)]
+    pub channel: Option<String>,
+
+    /// only messages sent by this user.
+    #[argh(option)]
+    pub user: Option<String>,
+
+    /// start at this time, HH:MM[:SS] into the archive or an RFC 3339 date.
+    #[argh(option)]
+    pub from: Option<TimeBound>,
+
+    /// end at this time, HH:MM[:SS] into the archive or an RFC 3339 date.
+    #[argh(option)]
+    pub to: Option<TimeBound>,
+
+    /// only messages containing this text.
+    #[argh(option)]
+    pub text: Option<String>,
+
+    /// print at most this many messages.
+    #[argh(option)]
+    pub limit: Option<u32>,
+}
+
+/// Where `serve` listens by default, twitch's plain irc port.
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:6667";
+
+impl Args {
+    /// Like [argh::from_env], with a missing channel as one more usage error.
+    pub fn from_env() -> Args {
+        let args: Args = argh::from_env();
+        let program = std::env::args().next().unwrap_or_default();
+        let command = Path::new(&program)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(&program);
+        if let Err(usage) = args.check(command) {
+            eprintln!("{usage}");
+            std::process::exit(1);
+        }
+        args
+    }
+
+    /// The usage of `command`, if a channel is needed but none was given.
+    fn check(&self, command: &str) -> Result<(), String> {
+        if !self.channel_name.is_empty() || self.from_stdin || self.command.is_some() {
+            return Ok(());
+        }
+        let usage = match Args::from_args(&[command], &["--help"]) {
+            Err(help) => help.output,
+            Ok(_) => unreachable!("--help always exits early"),
+        };
+        Err(format!(
+            "No channel given, it's needed without --from-stdin or a command.\n\n{usage}"
+        ))
+    }
+
+    /// The server picked by --host, --port & --transport.
+    pub fn endpoint(&self) -> Endpoint {
+        let twitch = Endpoint::twitch(self.transport);
+        Endpoint {
+            host: self.host.clone().unwrap_or(twitch.host),
+            port: self.port.unwrap_or(twitch.port),
+            transport: self.transport,
+        }
+    }
+
+    /// How --dedup collapses messages, if it was given.
+    pub fn dedup(&self) -> Option<Dedup> {
+        self.dedup.then(|| Dedup {
+            window: Duration::from_secs(self.dedup_window),
+            threshold: self.dedup_similarity,
+        })
+    }
+
+    pub fn pool(&self) -> Pool {
+        Pool {
+            connect_timeout: Duration::from_secs(self.connect_timeout),
+            new_connection_every: Duration::from_secs(self.connection_interval),
+            max_waiting_messages: self.max_waiting_messages,
+        }
+    }
+}
+
+fn parse_similarity(value: &str) -> Result<f64, String> {
+    match value.parse() {
+        Ok(similarity) if (0.0..=1.0).contains(&similarity) => Ok(similarity),
+        Ok(_) => Err("similarity must be from 0 to 1".to_string()),
+        Err(err) => Err(format!("{err}")),
+    }
+}
+
+fn parse_buffer_size(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(0) => Err("buffer size must be at least 1".to_string()),
+        Ok(size) => Ok(size),
+        Err(err) => Err(format!("{err}")),
+    }
+}
+
+/// Same defaults as the command line.
+impl Default for Args {
+    fn default() -> Args {
+        Args {
+            channel_name: String::new(),
+            log_file: None,
+            append: false,
+            from_stdin: false,
+            flush_interval: 1,
+            join_timeout: DEFAULT_JOIN_TIMEOUT.as_secs(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            display_policy: Policy::DropOldest,
+            dedup: false,
+            dedup_window: 10,
+            dedup_similarity: 0.9,
+            no_wrap: false,
+            activity: None,
+            activity_window: 5,
+            login: false,
+            credentials: None,
+            audit_log: None,
+            host: None,
+            port: None,
+            transport: Transport::Tls,
+            connect_timeout: 20,
+            connection_interval: 2,
+            max_waiting_messages: 5,
+            overlay: None,
+            #[cfg(feature = "sqlite")]
+            archive: None,
+            command: None,
+        }
+    }
+}
+
+#[test]
+fn endpoint_options_fill_in_twitch() {
+    let args = Args {
+        transport: Transport::Ws,
+        port: Some(8080),
+        ..Args::default()
+    };
+    assert_eq!(
+        args.endpoint().to_string(),
+        "ws://irc-ws.chat.twitch.tv:8080"
+    );
+    assert_eq!(Args::default().endpoint(), Endpoint::default());
+    assert_eq!(Args::default().pool(), Pool::default());
+}
+
+#[test]
+fn a_channel_is_needed_to_connect() {
+    let parse = |args: &[&str]| Args::from_args(&["twitch-ircv"], args).unwrap();
+    let usage = parse(&[]).check("twitch-ircv").unwrap_err();
+    assert!(usage.starts_with("No channel given"), "{usage}");
+    assert!(usage.contains("Usage: twitch-ircv"), "{usage}");
+    assert_eq!(parse(&["bread"]).check("twitch-ircv"), Ok(()));
+    assert_eq!(parse(&["--from-stdin"]).check("twitch-ircv"), Ok(()));
+    assert_eq!(parse(&["search", "chat.log"]).check("twitch-ircv"), Ok(()));
 }
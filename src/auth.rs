@@ -0,0 +1,167 @@
+//! Logging in, so the viewer can send messages
+//!
+//! The token never comes from the command line, where any other user could
+//! read it from the process list.
+use std::fmt;
+use std::path::{Path, PathBuf};
+use twitch_irc::login::StaticLoginCredentials;
+
+use crate::error::{Error, Result};
+
+/// Variable holding the login name.
+pub const LOGIN_VAR: &str = "TWITCH_IRCV_LOGIN";
+/// Variable holding the OAuth token, with or without the `oauth:` prefix.
+pub const TOKEN_VAR: &str = "TWITCH_IRCV_TOKEN";
+
+/// A login name & its OAuth token.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub login: String,
+    token: String,
+}
+
+/// Leaves out the token, so it can't end up in a log.
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("login", &self.login)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Credentials {
+    pub fn new(login: &str, token: &str) -> Credentials {
+        Credentials {
+            login: login.trim().to_lowercase(),
+            token: token.trim().trim_start_matches("oauth:").to_string(),
+        }
+    }
+
+    /// Reads [LOGIN_VAR] & [TOKEN_VAR], or else the credentials file.
+    ///
+    /// `file` defaults to [default_credentials_file].
+    pub fn load(file: Option<&Path>) -> Result<Credentials> {
+        if let Some(credentials) = Credentials::from_vars(|name| std::env::var(name).ok())? {
+            return Ok(credentials);
+        }
+        let path = match file {
+            Some(path) => path.to_path_buf(),
+            None => default_credentials_file().ok_or_else(|| {
+                Error::Credentials(format!("set {LOGIN_VAR} & {TOKEN_VAR} to log in"))
+            })?,
+        };
+        let text = std::fs::read_to_string(&path).map_err(|err| {
+            Error::Credentials(format!(
+                "set {LOGIN_VAR} & {TOKEN_VAR}, or write {}: {err}",
+                path.display()
+            ))
+        })?;
+        Credentials::parse(&text)
+    }
+
+    /// `None` if neither variable is set, `lookup` reads a variable.
+    fn from_vars<F>(lookup: F) -> Result<Option<Credentials>>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        match (lookup(LOGIN_VAR), lookup(TOKEN_VAR)) {
+            (Some(login), Some(token)) => Ok(Some(Credentials::new(&login, &token))),
+            (None, None) => Ok(None),
+            (Some(_), None) => Err(Error::Credentials(format!("{TOKEN_VAR} is not set"))),
+            (None, Some(_)) => Err(Error::Credentials(format!("{LOGIN_VAR} is not set"))),
+        }
+    }
+
+    /// Parses a credentials file.
+    ///
+    /// ```text
+    /// # Lines starting with # are ignored
+    /// login = bread
+    /// token = oauth:0123456789abcdefghijabcdefghij
+    /// ```
+    pub fn parse(text: &str) -> Result<Credentials> {
+        let (mut login, mut token) = (None, None);
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=').map(|(key, value)| (key.trim(), value)) {
+                Some(("login", value)) => login = Some(value),
+                Some(("token", value)) => token = Some(value),
+                _ => {
+                    return Err(Error::Credentials(format!(
+                        "expected `login = ...` or `token = ...`, got `{line}`"
+                    )))
+                }
+            }
+        }
+        match (login, token) {
+            (Some(login), Some(token)) => Ok(Credentials::new(login, token)),
+            (None, _) => Err(Error::Credentials(
+                "no login in credentials file".to_string(),
+            )),
+            (_, None) => Err(Error::Credentials(
+                "no token in credentials file".to_string(),
+            )),
+        }
+    }
+
+    /// The token without its `oauth:` prefix.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn into_login(self) -> StaticLoginCredentials {
+        StaticLoginCredentials::new(self.login, Some(self.token))
+    }
+}
+
+/// `$XDG_CONFIG_HOME/twitch-ircv/credentials`, or the same in `~/.config`.
+pub fn default_credentials_file() -> Option<PathBuf> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config.join("twitch-ircv").join("credentials"))
+}
+
+#[test]
+fn parse_credentials_file() {
+    let text = "# bread's bot\nlogin = Bread\ntoken = oauth:abc123\n";
+    let credentials = Credentials::parse(text).unwrap();
+    assert_eq!(credentials, Credentials::new("bread", "abc123"));
+    assert_eq!(
+        credentials.into_login().credentials.token.unwrap(),
+        "abc123"
+    );
+
+    assert!(Credentials::parse("login = bread\n").is_err());
+    assert!(Credentials::parse("login bread\ntoken = abc123\n").is_err());
+}
+
+#[test]
+fn credentials_from_vars() {
+    let vars = |login: Option<&str>, token: Option<&str>| {
+        let (login, token) = (login.map(String::from), token.map(String::from));
+        Credentials::from_vars(move |name| match name {
+            LOGIN_VAR => login.clone(),
+            TOKEN_VAR => token.clone(),
+            _ => None,
+        })
+    };
+    assert_eq!(
+        vars(Some("bread"), Some("oauth:abc123")).unwrap(),
+        Some(Credentials::new("bread", "abc123"))
+    );
+    assert_eq!(vars(None, None).unwrap(), None);
+    assert!(matches!(
+        vars(Some("bread"), None),
+        Err(Error::Credentials(_))
+    ));
+}
+
+#[test]
+fn debug_hides_token() {
+    let credentials = Credentials::new("bread", "abc123");
+    assert!(!format!("{credentials:?}").contains("abc123"));
+}
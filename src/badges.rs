@@ -1,11 +1,13 @@
 //! Types and Parsing for Twitch Badges
+use serde::Serialize;
 use std::fmt;
 use twitch_irc::message::Badge;
 
 /// Broadcaster/Moderator/Vip
 ///
 /// To my understanding these are mutually exclusive.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ChannelStatus {
     Broadcaster,
     Moderator,
@@ -18,7 +20,8 @@ pub enum ChannelStatus {
 /// the badge.
 /// This does not work for Founder badges, as for some reason the version number
 /// is listed as `"0"`.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Subscriber {
     Month(i32),
     Founder,
@@ -27,7 +30,7 @@ pub enum Subscriber {
 /// The badges in a chat message.
 ///
 /// This type is in development and may change.
-#[derive(Clone, Default, Debug, PartialEq, Eq)]
+#[derive(Clone, Default, Debug, PartialEq, Eq, Serialize)]
 pub struct Badges {
     pub channel_status: Option<ChannelStatus>,
     pub sub_badge_month: Option<Subscriber>,
@@ -36,7 +39,7 @@ pub struct Badges {
 }
 
 /// Parses a [Badge] array into [Badges] struct
-pub async fn parse_badges(badges: &[Badge]) -> Badges {
+pub fn parse_badges(badges: &[Badge]) -> Badges {
     let mut channel_status = None;
     let mut sub_badge_month = None;
     let mut partner = false;
@@ -59,8 +62,8 @@ pub async fn parse_badges(badges: &[Badge]) -> Badges {
     }
 }
 
-#[tokio::test]
-async fn test_parse_badges() {
+#[test]
+fn test_parse_badges() {
     let valid_strings = ["broadcaster", "moderator", "vip"];
     for s in valid_strings {
         let test_badges = [
@@ -73,7 +76,7 @@ async fn test_parse_badges() {
                 version: "1".to_string(),
             },
         ];
-        let permission_badge = parse_badges(&test_badges).await;
+        let permission_badge = parse_badges(&test_badges);
         let permission_badge = permission_badge.channel_status;
         assert!(permission_badge.is_some());
     }
@@ -88,7 +91,7 @@ async fn test_parse_badges() {
             version: "90210".to_string(),
         },
     ];
-    let sub_badge = parse_badges(&test_badges).await;
+    let sub_badge = parse_badges(&test_badges);
     let sub_badge = sub_badge.sub_badge_month;
     assert_eq!(sub_badge, Some(Subscriber::Month(90210)));
 
@@ -102,14 +105,14 @@ async fn test_parse_badges() {
             version: "90".to_string(),
         },
     ];
-    let sub_badge = parse_badges(&test_badges).await;
+    let sub_badge = parse_badges(&test_badges);
     assert!(sub_badge.partner);
 
     let test_badges = [Badge {
         name: "founder".to_string(),
         version: "0".to_string(),
     }];
-    let sub_badge = parse_badges(&test_badges).await;
+    let sub_badge = parse_badges(&test_badges);
     assert_eq!(Some(Subscriber::Founder), sub_badge.sub_badge_month);
 }
 
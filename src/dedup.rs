@@ -0,0 +1,135 @@
+//! Collapsing copypasta waves into one line
+//!
+//! Only the display is collapsed, the log keeps every message.
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+use twitch_irc::message::PrivmsgMessage;
+
+/// When consecutive messages count as the same.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dedup {
+    /// The longest gap between two messages of a run.
+    pub window: Duration,
+    /// From 0 to 1, by edit distance. 1 only collapses messages that are the
+    /// same apart from case & spacing.
+    pub threshold: f64,
+}
+
+impl Default for Dedup {
+    fn default() -> Dedup {
+        Dedup {
+            window: Duration::from_secs(10),
+            threshold: 0.9,
+        }
+    }
+}
+
+/// Follows the current run of similar consecutive messages.
+#[derive(Clone, Debug)]
+pub struct Collapser {
+    dedup: Dedup,
+    run: Option<Run>,
+}
+
+#[derive(Clone, Debug)]
+struct Run {
+    /// The first message, normalized, the others are compared to it.
+    text: Vec<char>,
+    last_sent: DateTime<Utc>,
+    count: u64,
+}
+
+impl Collapser {
+    pub fn new(dedup: Dedup) -> Collapser {
+        Collapser { dedup, run: None }
+    }
+
+    /// How many messages in a row, `msg` included, were alike. `1` starts a
+    /// new run.
+    pub fn check(&mut self, msg: &PrivmsgMessage) -> u64 {
+        let text = normalize(&msg.message_text);
+        let sent = msg.server_timestamp;
+        if let Some(run) = &mut self.run {
+            let gap = sent.signed_duration_since(run.last_sent);
+            let in_window = gap.to_std().map_or(true, |gap| gap <= self.dedup.window);
+            if in_window && similarity(&run.text, &text) >= self.dedup.threshold {
+                run.last_sent = sent;
+                run.count += 1;
+                return run.count;
+            }
+        }
+        self.run = Some(Run {
+            text,
+            last_sent: sent,
+            count: 1,
+        });
+        1
+    }
+
+    /// Ends the run, e.g. because something else was shown after it.
+    pub fn reset(&mut self) {
+        self.run = None;
+    }
+}
+
+/// Lowercase, with runs of whitespace made one space.
+fn normalize(text: &str) -> Vec<char> {
+    let words = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    words.to_lowercase().chars().collect()
+}
+
+/// How alike `a` & `b` are, from 0 to 1, by their edit distance.
+pub fn similarity(a: &[char], b: &[char]) -> f64 {
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - edit_distance(a, b) as f64 / longest as f64
+}
+
+/// Levenshtein distance, in characters.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+#[test]
+fn similar_runs_are_counted() {
+    let start = Utc::now();
+    let mut msg = crate::setup::make_privmsg_example();
+    let mut collapser = Collapser::new(Dedup::default());
+    let mut check = |text: &str, secs: i64| {
+        msg.message_text = text.to_string();
+        msg.server_timestamp = start + chrono::Duration::seconds(secs);
+        collapser.check(&msg)
+    };
+    assert_eq!(check("bread bread bread", 0), 1);
+    assert_eq!(check("Bread  bread bread", 1), 2);
+    // Close enough
+    assert_eq!(check("bread bread bread!", 2), 3);
+    assert_eq!(check("toast", 3), 1);
+    assert_eq!(check("toast", 3), 2);
+    // Too late
+    assert_eq!(check("toast", 20), 1);
+    collapser.reset();
+    assert_eq!(collapser.check(&msg), 1);
+}
+
+#[test]
+fn similarity_is_by_edit_distance() {
+    let chars = |s: &str| s.chars().collect::<Vec<_>>();
+    assert_eq!(similarity(&chars(""), &chars("")), 1.0);
+    assert_eq!(similarity(&chars("abcd"), &chars("abcd")), 1.0);
+    assert_eq!(similarity(&chars("abcd"), &chars("abce")), 0.75);
+    assert_eq!(similarity(&chars("abcd"), &chars("")), 0.0);
+    assert_eq!(edit_distance(&chars("kitten"), &chars("sitting")), 3);
+}
@@ -0,0 +1,257 @@
+//! Where the irc client connects to, & how
+//!
+//! twitch-irc builds its connections without any configuration, so the
+//! endpoint is process wide. It's set for as long as the guard of
+//! [set_endpoint] lives, & has to be set before a client connects.
+use async_trait::async_trait;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use twitch_irc::login::LoginCredentials;
+use twitch_irc::transport::tcp::{MakeConnection, TCPTransportConnectError};
+use twitch_irc::ClientConfig;
+
+/// How irc is carried to the server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Transport {
+    Tcp,
+    #[default]
+    Tls,
+    /// Plain WebSocket.
+    Ws,
+    /// WebSocket over TLS.
+    Wss,
+}
+
+impl Transport {
+    /// The port twitch uses for this transport.
+    pub fn default_port(self) -> u16 {
+        match self {
+            Transport::Tcp => 6667,
+            Transport::Tls => 6697,
+            Transport::Ws => 80,
+            Transport::Wss => 443,
+        }
+    }
+
+    /// The host twitch uses for this transport.
+    pub fn default_host(self) -> &'static str {
+        match self {
+            Transport::Tcp | Transport::Tls => "irc.chat.twitch.tv",
+            Transport::Ws | Transport::Wss => "irc-ws.chat.twitch.tv",
+        }
+    }
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(Transport::Tcp),
+            "tls" => Ok(Transport::Tls),
+            "ws" => Ok(Transport::Ws),
+            "wss" => Ok(Transport::Wss),
+            _ => Err(format!(
+                "unknown transport `{s}`, expected tcp, tls, ws or wss"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Tcp => write!(f, "tcp"),
+            Transport::Tls => write!(f, "tls"),
+            Transport::Ws => write!(f, "ws"),
+            Transport::Wss => write!(f, "wss"),
+        }
+    }
+}
+
+/// An irc server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
+    pub transport: Transport,
+}
+
+impl Endpoint {
+    /// Twitch's own server for `transport`.
+    pub fn twitch(transport: Transport) -> Endpoint {
+        Endpoint {
+            host: transport.default_host().to_string(),
+            port: transport.default_port(),
+            transport,
+        }
+    }
+}
+
+/// Twitch itself, over TLS.
+impl Default for Endpoint {
+    fn default() -> Endpoint {
+        Endpoint::twitch(Transport::default())
+    }
+}
+
+/// Reads like the address it connects to, `wss://irc-ws.chat.twitch.tv:443`.
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://{}:{}", self.transport, self.host, self.port)
+    }
+}
+
+static ENDPOINT: RwLock<Option<Endpoint>> = RwLock::new(None);
+
+/// Connections made from now on go to `endpoint`, until the returned guard
+/// is dropped.
+#[must_use = "the endpoint is only set while the guard lives"]
+pub fn set_endpoint(endpoint: Endpoint) -> EndpointGuard {
+    let previous = ENDPOINT.write().unwrap().replace(endpoint);
+    EndpointGuard { previous }
+}
+
+/// Puts the endpoint from before [set_endpoint] back when dropped.
+pub struct EndpointGuard {
+    previous: Option<Endpoint>,
+}
+
+impl Drop for EndpointGuard {
+    fn drop(&mut self) {
+        *ENDPOINT.write().unwrap() = self.previous.take();
+    }
+}
+
+/// Only one test at a time can point the endpoint at its server.
+#[cfg(test)]
+pub(crate) static ENDPOINT_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// The endpoint of a test, set while it holds [ENDPOINT_LOCK].
+#[cfg(test)]
+pub(crate) struct TestEndpoint {
+    // Fields drop in order, the endpoint is put back before unlocking
+    _endpoint: EndpointGuard,
+    _lock: tokio::sync::MutexGuard<'static, ()>,
+}
+
+/// Points connections at `endpoint` until the test is done with it.
+#[cfg(test)]
+pub(crate) async fn test_endpoint(endpoint: Endpoint) -> TestEndpoint {
+    let lock = ENDPOINT_LOCK.lock().await;
+    TestEndpoint {
+        _endpoint: set_endpoint(endpoint),
+        _lock: lock,
+    }
+}
+
+/// The endpoint set with [set_endpoint], twitch by default.
+pub fn endpoint() -> Endpoint {
+    ENDPOINT.read().unwrap().clone().unwrap_or_default()
+}
+
+/// How the client opens & uses its connections.
+///
+/// These are the knobs of twitch-irc's connection pool, with its defaults.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pool {
+    /// Give up on a connection attempt after this long, including the TLS &
+    /// WebSocket handshakes.
+    pub connect_timeout: Duration,
+    /// Open at most one new connection this often, e.g. when reconnecting.
+    pub new_connection_every: Duration,
+    /// Open another connection once this many sent messages are in flight.
+    pub max_waiting_messages: usize,
+}
+
+impl Default for Pool {
+    fn default() -> Pool {
+        Pool {
+            connect_timeout: Duration::from_secs(20),
+            new_connection_every: Duration::from_secs(2),
+            max_waiting_messages: 5,
+        }
+    }
+}
+
+impl Pool {
+    pub fn apply<L: LoginCredentials>(&self, config: &mut ClientConfig<L>) {
+        config.connect_timeout = self.connect_timeout;
+        config.new_connection_every = self.new_connection_every;
+        config.max_waiting_messages_per_connection = self.max_waiting_messages;
+    }
+}
+
+/// A plain, TLS or WebSocket connection.
+pub trait Socket: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Sync + Unpin> Socket for S {}
+
+/// Connects to [endpoint()].
+pub struct Configured;
+
+#[async_trait]
+impl MakeConnection for Configured {
+    type Socket = Box<dyn Socket>;
+
+    async fn new_socket() -> Result<Self::Socket, TCPTransportConnectError> {
+        use tokio_native_tls::native_tls;
+
+        let endpoint = endpoint();
+        let Endpoint {
+            host,
+            port,
+            transport,
+        } = &endpoint;
+        match transport {
+            Transport::Tcp | Transport::Tls => {
+                let socket = TcpStream::connect((host.as_str(), *port)).await?;
+                if *transport == Transport::Tcp {
+                    return Ok(Box::new(socket));
+                }
+                let connector =
+                    tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+                Ok(Box::new(connector.connect(host, socket).await?))
+            }
+            Transport::Ws | Transport::Wss => {
+                let (ws, _) = async_tungstenite::tokio::connect_async(endpoint.to_string())
+                    .await
+                    .map_err(io::Error::other)?;
+                Ok(Box::new(crate::websocket::into_stream(ws)))
+            }
+        }
+    }
+}
+
+#[test]
+fn endpoints_default_to_twitch() {
+    assert_eq!(
+        Endpoint::default().to_string(),
+        "tls://irc.chat.twitch.tv:6697"
+    );
+    assert_eq!(
+        Endpoint::twitch("wss".parse().unwrap()).to_string(),
+        "wss://irc-ws.chat.twitch.tv:443"
+    );
+    assert!("https".parse::<Transport>().is_err());
+}
+
+#[tokio::test]
+async fn endpoints_are_put_back() {
+    let _lock = ENDPOINT_LOCK.lock().await;
+    let before = endpoint();
+    let local = Endpoint {
+        host: "localhost".to_string(),
+        port: 6667,
+        transport: Transport::Tcp,
+    };
+    let guard = set_endpoint(local.clone());
+    assert_eq!(endpoint(), local);
+    drop(guard);
+    assert_eq!(endpoint(), before);
+}
@@ -0,0 +1,134 @@
+//! Errors that stop the viewer, & the exit codes they map to
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Reasons for the viewer to stop before the chat ends.
+///
+/// Each variant maps to an exit code, see [Error::exit_code].
+#[derive(Debug)]
+pub enum Error {
+    /// Twitch sent a NOTICE listed in [crate::pretty_print::FATAL_NOTICE_IDS].
+    Notice(String),
+    /// The channel join wasn't confirmed in time, usually a misspelled channel.
+    JoinTimeout(String),
+    /// The channel name can't be a twitch login.
+    InvalidChannel(String),
+    /// The log file couldn't be opened.
+    LogOpen { path: PathBuf, source: io::Error },
+    /// Writing to the log file failed, e.g. a full disk.
+    LogWrite(io::Error),
+    /// Writing the chat to the output failed.
+    Output(io::Error),
+    /// Reading irc from stdin or a replayed log failed.
+    Input(io::Error),
+    /// A line read from stdin or a replayed log isn't a valid irc message.
+    Parse(String),
+    /// Logging in was asked for, but there are no usable credentials.
+    Credentials(String),
+    /// The replay or overlay server couldn't listen on its address.
+    Listen { addr: String, source: io::Error },
+    /// The SQLite archive couldn't be opened, written or queried.
+    #[cfg(feature = "sqlite")]
+    Archive {
+        path: PathBuf,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Status the process should exit with, from 2 as argh exits with 1 on
+    /// usage errors.
+    ///
+    /// Keep the README & the `error_code`s on `Args` in sync
+    /// with this.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Notice(_) => 2,
+            Error::JoinTimeout(_) => 3,
+            Error::InvalidChannel(_) => 4,
+            Error::LogOpen { .. } => 5,
+            Error::LogWrite(_) | Error::Output(_) => 6,
+            Error::Input(_) | Error::Parse(_) => 7,
+            Error::Credentials(_) => 8,
+            Error::Listen { .. } => 9,
+            #[cfg(feature = "sqlite")]
+            Error::Archive { .. } => 10,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Notice(text) => write!(f, "Twitch refused the connection: {text}"),
+            Error::JoinTimeout(channel) => write!(
+                f,
+                "Could not join #{channel}, check that the channel exists and you are online"
+            ),
+            Error::InvalidChannel(reason) => write!(f, "Invalid channel name: {reason}"),
+            Error::LogOpen { path, source } => {
+                write!(f, "Could not open log file {}: {source}", path.display())
+            }
+            Error::LogWrite(err) => write!(f, "Could not write to the log file: {err}"),
+            Error::Output(err) => write!(f, "Could not write the chat: {err}"),
+            Error::Input(err) => write!(f, "Could not read the input: {err}"),
+            Error::Parse(line) => write!(f, "Input is not valid irc: {line}"),
+            Error::Credentials(reason) => write!(f, "Could not log in: {reason}"),
+            Error::Listen { addr, source } => write!(f, "Could not listen on {addr}: {source}"),
+            #[cfg(feature = "sqlite")]
+            Error::Archive { path, source } => {
+                write!(f, "Could not use the archive {}: {source}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::LogOpen { source, .. } | Error::Listen { source, .. } => Some(source),
+            Error::LogWrite(err) | Error::Output(err) | Error::Input(err) => Some(err),
+            #[cfg(feature = "sqlite")]
+            Error::Archive { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn exit_codes_are_distinct_per_class() {
+    let errors = [
+        Error::Notice(String::new()),
+        Error::JoinTimeout(String::new()),
+        Error::InvalidChannel(String::new()),
+        Error::LogOpen {
+            path: PathBuf::new(),
+            source: io::ErrorKind::PermissionDenied.into(),
+        },
+        Error::LogWrite(io::ErrorKind::Other.into()),
+        Error::Input(io::ErrorKind::Other.into()),
+        Error::Credentials(String::new()),
+        Error::Listen {
+            addr: String::new(),
+            source: io::ErrorKind::AddrInUse.into(),
+        },
+    ];
+    let codes: Vec<u8> = errors.iter().map(Error::exit_code).collect();
+    assert_eq!(codes, [2, 3, 4, 5, 6, 7, 8, 9]);
+    // Left for usage errors
+    assert!(!codes.contains(&1));
+    #[cfg(feature = "sqlite")]
+    assert_eq!(
+        Error::Archive {
+            path: PathBuf::new(),
+            source: "locked".into(),
+        }
+        .exit_code(),
+        10
+    );
+    assert_eq!(Error::Output(io::ErrorKind::Other.into()).exit_code(), 6);
+    assert_eq!(Error::Parse(String::new()).exit_code(), 7);
+}
@@ -0,0 +1,806 @@
+//! Exporting a log as a page to share
+//!
+//! The log is first read into a [Transcript], with moderation applied to
+//! the messages it hit, & then written out in the [ExportFormat] asked for.
+use chrono::{DateTime, Duration, Utc};
+use std::fmt;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::str::FromStr;
+use twitch_irc::message::{ClearChatAction, PrivmsgMessage, ServerMessage};
+
+use crate::args::ExportArgs;
+use crate::badges::parse_badges;
+use crate::error::{Error, Result};
+use crate::overlay::hex_color;
+use crate::pretty_print::{elapsed, Formatter};
+use crate::room_state::{short_duration, RoomState};
+use crate::setup::filein_to_smsg;
+
+/// What a log can be exported as.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A self-contained page with search.
+    #[default]
+    Html,
+    /// The lines the viewer prints, without colors.
+    Text,
+    Markdown,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(ExportFormat::Html),
+            "text" => Ok(ExportFormat::Text),
+            "markdown" | "md" => Ok(ExportFormat::Markdown),
+            _ => Err(format!(
+                "unknown format `{s}`, expected html, text or markdown"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportFormat::Html => write!(f, "html"),
+            ExportFormat::Text => write!(f, "text"),
+            ExportFormat::Markdown => write!(f, "markdown"),
+        }
+    }
+}
+
+/// One line of a [Transcript].
+#[derive(Clone, Debug)]
+pub enum Entry {
+    Chat {
+        msg: Box<PrivmsgMessage>,
+        /// Deleted by a moderator, or its sender was timed out or banned.
+        removed: bool,
+    },
+    /// Moderation, subs, raids & chat mode changes, as a sentence.
+    Event {
+        /// `None` for chat modes, ROOMSTATE has no timestamp.
+        time: Option<DateTime<Utc>>,
+        /// Who it was about, if anyone.
+        user: Option<String>,
+        kind: EntryKind,
+        text: String,
+    },
+}
+
+/// What an [Entry] is, to search by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    Chat,
+    /// Subs, resubs & gifted subs.
+    Sub,
+    Raid,
+    /// Any other USERNOTICE, like announcements.
+    Notice,
+    Ban,
+    Timeout,
+    Clear,
+    /// A chat mode change.
+    Mode,
+}
+
+impl EntryKind {
+    /// What a USERNOTICE with this `msg-id` is.
+    fn of_user_notice(event_id: &str) -> EntryKind {
+        match event_id {
+            "sub"
+            | "resub"
+            | "subgift"
+            | "submysterygift"
+            | "anonsubgift"
+            | "anonsubmysterygift"
+            | "giftpaidupgrade"
+            | "anongiftpaidupgrade"
+            | "primepaidupgrade" => EntryKind::Sub,
+            "raid" => EntryKind::Raid,
+            _ => EntryKind::Notice,
+        }
+    }
+}
+
+impl FromStr for EntryKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "chat" => Ok(EntryKind::Chat),
+            "sub" => Ok(EntryKind::Sub),
+            "raid" => Ok(EntryKind::Raid),
+            "notice" => Ok(EntryKind::Notice),
+            "ban" => Ok(EntryKind::Ban),
+            "timeout" => Ok(EntryKind::Timeout),
+            "clear" => Ok(EntryKind::Clear),
+            "mode" => Ok(EntryKind::Mode),
+            _ => Err(format!(
+                "unknown event type `{s}`, expected chat, sub, raid, notice, ban, timeout, \
+                 clear or mode"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EntryKind::Chat => "chat",
+            EntryKind::Sub => "sub",
+            EntryKind::Raid => "raid",
+            EntryKind::Notice => "notice",
+            EntryKind::Ban => "ban",
+            EntryKind::Timeout => "timeout",
+            EntryKind::Clear => "clear",
+            EntryKind::Mode => "mode",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Entry {
+    pub fn time(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Entry::Chat { msg, .. } => Some(msg.server_timestamp),
+            Entry::Event { time, .. } => *time,
+        }
+    }
+
+    /// The login of who said it, or who it was about.
+    pub fn user(&self) -> Option<&str> {
+        match self {
+            Entry::Chat { msg, .. } => Some(&msg.sender.login),
+            Entry::Event { user, .. } => user.as_deref(),
+        }
+    }
+
+    pub fn kind(&self) -> EntryKind {
+        match self {
+            Entry::Chat { .. } => EntryKind::Chat,
+            Entry::Event { kind, .. } => *kind,
+        }
+    }
+
+    /// What was said, or what happened.
+    pub fn text(&self) -> &str {
+        match self {
+            Entry::Chat { msg, .. } => &msg.message_text,
+            Entry::Event { text, .. } => text,
+        }
+    }
+
+    /// The line the viewer would have shown, `time` is when it happened,
+    /// see [Transcript::times].
+    pub fn line(&self, time: DateTime<Utc>, formatter: &Formatter) -> String {
+        match self {
+            Entry::Chat { msg, removed } => {
+                let marker = if *removed { " [removed]" } else { "" };
+                format!("{}{marker}", formatter.chat_message(msg))
+            }
+            Entry::Event { text, .. } => formatter.event(time, text),
+        }
+    }
+}
+
+/// Which earlier chat messages a moderator removed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Removal {
+    /// One message, by id.
+    Message(String),
+    /// Everything a user said, they were timed out or banned.
+    User(String),
+    /// Everything, chat was cleared.
+    All,
+}
+
+impl Removal {
+    pub fn hits(&self, msg: &PrivmsgMessage) -> bool {
+        match self {
+            Removal::Message(id) => msg.message_id == *id,
+            Removal::User(login) => msg.sender.login == *login,
+            Removal::All => true,
+        }
+    }
+}
+
+/// Turns the messages of a log into [Entry]s, one at a time.
+#[derive(Clone, Debug, Default)]
+pub struct EntryReader {
+    room_state: RoomState,
+}
+
+impl EntryReader {
+    /// What `message` removes from the chat before it, & the entries it
+    /// adds after that.
+    pub fn read(&mut self, message: ServerMessage) -> (Option<Removal>, Vec<Entry>) {
+        let event = |time, user, kind, text| Entry::Event {
+            time,
+            user,
+            kind,
+            text,
+        };
+        match message {
+            ServerMessage::Privmsg(msg) => {
+                let chat = Entry::Chat {
+                    msg: Box::new(msg),
+                    removed: false,
+                };
+                (None, vec![chat])
+            }
+            ServerMessage::ClearMsg(msg) => (Some(Removal::Message(msg.message_id)), vec![]),
+            ServerMessage::ClearChat(msg) => {
+                let (removal, kind, user, text) = match msg.action {
+                    ClearChatAction::ChatCleared => (
+                        Removal::All,
+                        EntryKind::Clear,
+                        None,
+                        "Chat was cleared".to_string(),
+                    ),
+                    ClearChatAction::UserBanned { user_login, .. } => {
+                        let text = format!("{user_login} was banned");
+                        let removal = Removal::User(user_login.clone());
+                        (removal, EntryKind::Ban, Some(user_login), text)
+                    }
+                    ClearChatAction::UserTimedOut {
+                        user_login,
+                        timeout_length,
+                        ..
+                    } => {
+                        let length = short_duration(&timeout_length);
+                        let text = format!("{user_login} was timed out for {length}");
+                        let removal = Removal::User(user_login.clone());
+                        (removal, EntryKind::Timeout, Some(user_login), text)
+                    }
+                };
+                let entry = event(Some(msg.server_timestamp), user, kind, text);
+                (Some(removal), vec![entry])
+            }
+            ServerMessage::UserNotice(msg) => {
+                let text = match msg.message_text {
+                    Some(text) => format!("{}: {text}", msg.system_message),
+                    None => msg.system_message,
+                };
+                let kind = EntryKind::of_user_notice(&msg.event_id);
+                let entry = event(
+                    Some(msg.server_timestamp),
+                    Some(msg.sender.login),
+                    kind,
+                    text,
+                );
+                (None, vec![entry])
+            }
+            ServerMessage::RoomState(msg) => {
+                let changes = self.room_state.update(&msg).into_iter();
+                let modes =
+                    changes.map(|change| event(None, None, EntryKind::Mode, change.to_string()));
+                (None, modes.collect())
+            }
+            _ => (None, vec![]),
+        }
+    }
+}
+
+/// The chat of a log, in order.
+#[derive(Clone, Debug, Default)]
+pub struct Transcript {
+    /// The channel of the first message, if any.
+    pub channel: Option<String>,
+    /// When the first message was sent, times are shown relative to it.
+    pub start: Option<DateTime<Utc>>,
+    pub entries: Vec<Entry>,
+}
+
+impl Transcript {
+    /// Reads a log written with [log_v0](crate::logging::log_v0).
+    pub fn read<R: BufRead>(log: R) -> Result<Transcript> {
+        let mut transcript = Transcript::default();
+        let mut reader = EntryReader::default();
+        for message in filein_to_smsg(log) {
+            transcript.push(message?, &mut reader);
+        }
+        transcript.start = transcript.entries.iter().find_map(Entry::time);
+        Ok(transcript)
+    }
+
+    /// When each entry happened.
+    ///
+    /// Chat modes have no time of their own, they count as happening at the
+    /// entry before them.
+    pub fn times(&self) -> Vec<DateTime<Utc>> {
+        let mut last_time = self.start.unwrap_or_default();
+        let times = self.entries.iter().map(|entry| {
+            last_time = entry.time().unwrap_or(last_time);
+            last_time
+        });
+        times.collect()
+    }
+
+    /// Keeps only the entries `filter` lets through.
+    pub fn retain(&mut self, filter: &Filter) {
+        let start = self.start.unwrap_or_default();
+        let mut times = self.times().into_iter();
+        self.entries.retain(|entry| {
+            let time = times.next().expect("A time per entry");
+            filter.keeps(entry, time, start)
+        });
+    }
+
+    /// The lines the viewer would have shown, one per entry.
+    pub fn lines(&self, formatter: &Formatter) -> Vec<String> {
+        let lines = self.entries.iter().zip(self.times());
+        lines
+            .map(|(entry, time)| entry.line(time, formatter))
+            .collect()
+    }
+
+    fn push(&mut self, message: ServerMessage, reader: &mut EntryReader) {
+        let channel = match &message {
+            ServerMessage::Privmsg(msg) => Some(&msg.channel_login),
+            ServerMessage::UserNotice(msg) => Some(&msg.channel_login),
+            ServerMessage::ClearChat(msg) => Some(&msg.channel_login),
+            ServerMessage::ClearMsg(msg) => Some(&msg.channel_login),
+            ServerMessage::RoomState(msg) => Some(&msg.channel_login),
+            _ => None,
+        };
+        if self.channel.is_none() {
+            self.channel = channel.cloned();
+        }
+        let (removal, entries) = reader.read(message);
+        if let Some(removal) = removal {
+            self.remove(|chat| removal.hits(chat));
+        }
+        self.entries.extend(entries);
+    }
+
+    /// Marks the earlier chat messages `hit` matches as removed.
+    fn remove<F: Fn(&PrivmsgMessage) -> bool>(&mut self, hit: F) {
+        for entry in &mut self.entries {
+            if let Entry::Chat { msg, removed } = entry {
+                *removed |= hit(msg);
+            }
+        }
+    }
+}
+
+/// Which part of a [Transcript] to export.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub from: Option<TimeBound>,
+    pub to: Option<TimeBound>,
+    /// Logins, case-insensitive. Events count as theirs if they were about
+    /// them. Everyone if empty.
+    pub users: Vec<String>,
+}
+
+impl Filter {
+    /// Whether `entry`, which happened at `time` in a log starting at
+    /// `start`, is let through.
+    pub fn keeps(&self, entry: &Entry, time: DateTime<Utc>, start: DateTime<Utc>) -> bool {
+        let from = self.from.map(|bound| bound.resolve(start));
+        let to = self.to.map(|bound| bound.resolve(start));
+        let in_range = from.is_none_or(|from| time >= from) && to.is_none_or(|to| time <= to);
+        let has_user = self.users.is_empty()
+            || entry.user().is_some_and(|user| {
+                let mut users = self.users.iter();
+                users.any(|wanted| wanted.eq_ignore_ascii_case(user))
+            });
+        in_range && has_user
+    }
+}
+
+/// One end of a time range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeBound {
+    /// A point in time, RFC 3339 like `2023-02-10T03:33:20Z`.
+    At(DateTime<Utc>),
+    /// Since the start of the log, `HH:MM[:SS]` like the viewer shows.
+    After(Duration),
+}
+
+impl TimeBound {
+    /// The time this is, in a log starting at `start`.
+    pub(crate) fn resolve(self, start: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            TimeBound::At(time) => time,
+            TimeBound::After(offset) => start + offset,
+        }
+    }
+}
+
+impl FromStr for TimeBound {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+            return Ok(TimeBound::At(time.with_timezone(&Utc)));
+        }
+        let parts: Option<Vec<i64>> = s.split(':').map(|part| part.parse().ok()).collect();
+        match parts.as_deref() {
+            Some(&[h, m]) if h >= 0 && (0..60).contains(&m) => {
+                Ok(TimeBound::After(Duration::hours(h) + Duration::minutes(m)))
+            }
+            Some(&[h, m, sec]) if h >= 0 && (0..60).contains(&m) && (0..60).contains(&sec) => {
+                Ok(TimeBound::After(
+                    Duration::hours(h) + Duration::minutes(m) + Duration::seconds(sec),
+                ))
+            }
+            _ => Err(format!(
+                "invalid time `{s}`, expected HH:MM[:SS] into the log or an RFC 3339 date"
+            )),
+        }
+    }
+}
+
+/// Exports the log of `args`.
+pub fn export(args: ExportArgs) -> Result<()> {
+    let log = File::open(&args.log_file).map_err(|source| Error::LogOpen {
+        path: args.log_file.clone(),
+        source,
+    })?;
+    let mut transcript = Transcript::read(io::BufReader::new(log))?;
+    transcript.retain(&Filter {
+        from: args.from,
+        to: args.to,
+        users: args.user,
+    });
+    let out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path).map_err(Error::Output)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut out = io::BufWriter::new(out);
+    let written = match args.format {
+        ExportFormat::Html => write_html(&transcript, &mut out),
+        ExportFormat::Text => write_text(&transcript, &mut out),
+        ExportFormat::Markdown => write_markdown(&transcript, &mut out),
+    };
+    written.and_then(|()| out.flush()).map_err(Error::Output)
+}
+
+/// Writes `transcript` as a page that needs nothing but a browser.
+pub fn write_html<W: Write>(transcript: &Transcript, out: &mut W) -> io::Result<()> {
+    let title = match &transcript.channel {
+        Some(channel) => format!("#{channel} chat"),
+        None => "Chat".to_string(),
+    };
+    let mut lines = String::new();
+    let mut day = None;
+    for entry in &transcript.entries {
+        if let Some(time) = entry.time() {
+            let date = time.date_naive();
+            if day.replace(date) != Some(date) {
+                writeln!(lines, "<h2 class=\"day\">{date}</h2>").unwrap();
+            }
+        }
+        let time = match entry.time() {
+            Some(time) => format!(
+                "<time datetime=\"{}\">{}</time>",
+                time.to_rfc3339(),
+                time.format("%H:%M:%S")
+            ),
+            None => "<time></time>".to_string(),
+        };
+        match entry {
+            Entry::Chat { msg, removed } => {
+                let class = match (removed, msg.is_action) {
+                    (true, true) => "line chat action removed",
+                    (true, false) => "line chat removed",
+                    (false, true) => "line chat action",
+                    (false, false) => "line chat",
+                };
+                let color = match msg.name_color {
+                    Some(color) => format!(" style=\"color: {}\"", hex_color(color)),
+                    None => String::new(),
+                };
+                let separator = if msg.is_action { " " } else { ": " };
+                writeln!(
+                    lines,
+                    "<div class=\"{class}\">{time} <span class=\"badges\">{}</span>\
+                     <span class=\"name\"{color}>{}</span>{separator}\
+                     <span class=\"text\">{}</span></div>",
+                    parse_badges(&msg.badges),
+                    escape(&msg.sender.name),
+                    escape(&msg.message_text),
+                )
+                .unwrap();
+            }
+            Entry::Event { text, .. } => {
+                writeln!(
+                    lines,
+                    "<div class=\"line event\">{time} <span class=\"text\">* {}</span></div>",
+                    escape(text)
+                )
+                .unwrap();
+            }
+        }
+    }
+    let title = escape(&title);
+    write!(
+        out,
+        "{}",
+        HTML_PAGE
+            .replace("{title}", &title)
+            .replace("{lines}", &lines)
+    )
+}
+
+/// Writes `transcript` as the viewer would have shown it, without colors.
+pub fn write_text<W: Write>(transcript: &Transcript, out: &mut W) -> io::Result<()> {
+    let formatter = Formatter::new(transcript.start.unwrap_or_default()).without_color();
+    for line in transcript.lines(&formatter) {
+        writeln!(out, "{line}")?;
+    }
+    Ok(())
+}
+
+/// Writes `transcript` as a Markdown list, one item per line.
+pub fn write_markdown<W: Write>(transcript: &Transcript, out: &mut W) -> io::Result<()> {
+    match &transcript.channel {
+        Some(channel) => writeln!(out, "# #{} chat\n", escape_markdown(channel))?,
+        None => writeln!(out, "# Chat\n")?,
+    }
+    let start = transcript.start.unwrap_or_default();
+    for (entry, time) in transcript.entries.iter().zip(transcript.times()) {
+        let elapsed = elapsed(time, start);
+        match entry {
+            Entry::Chat { msg, removed } => {
+                let separator = if msg.is_action { " " } else { ": " };
+                let line = format!(
+                    "{}**{}**{separator}{}",
+                    parse_badges(&msg.badges),
+                    escape_markdown(&msg.sender.name),
+                    escape_markdown(&msg.message_text)
+                );
+                if *removed {
+                    writeln!(out, "- `{elapsed}` ~~{line}~~")?;
+                } else {
+                    writeln!(out, "- `{elapsed}` {line}")?;
+                }
+            }
+            Entry::Event { text, .. } => {
+                writeln!(out, "- `{elapsed}` *{}*", escape_markdown(text))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Backslash-escapes what Markdown would otherwise format.
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_{}[]<>()#+-.!|~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escapes text for use in HTML, in elements & quoted attributes.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// [write_html]'s page, `{title}` & `{lines}` are filled in.
+const HTML_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>
+  body { margin: 0; background: #18181b; color: #efeff1; font: 14px/1.5 sans-serif; }
+  header { position: sticky; top: 0; padding: 8px 16px; background: #0e0e10; display: flex; gap: 12px; align-items: center; }
+  header h1 { margin: 0; font-size: 16px; }
+  #search { flex: 1; max-width: 400px; padding: 4px 8px; }
+  main { padding: 8px 16px; }
+  .day { font-size: 13px; color: #adadb8; border-bottom: 1px solid #3a3a3d; }
+  .line { overflow-wrap: anywhere; }
+  time { color: #adadb8; font-family: monospace; margin-right: 4px; }
+  .name { font-weight: bold; color: #b9a3e3; }
+  .action .text { font-style: italic; }
+  .removed .name, .removed .text { text-decoration: line-through; opacity: 0.6; }
+  .event { color: #adadb8; font-style: italic; }
+  .hidden { display: none; }
+</style>
+</head>
+<body>
+<header>
+<h1>{title}</h1>
+<input id="search" type="search" placeholder="Search messages & names">
+<span id="count"></span>
+</header>
+<main>
+{lines}</main>
+<script>
+  const lines = document.querySelectorAll(".line");
+  const search = document.getElementById("search");
+  const count = document.getElementById("count");
+  search.addEventListener("input", () => {
+    const query = search.value.toLowerCase();
+    let shown = 0;
+    for (const line of lines) {
+      const hit = line.textContent.toLowerCase().includes(query);
+      line.classList.toggle("hidden", !hit);
+      shown += hit;
+    }
+    count.textContent = query ? `${shown} of ${lines.length}` : "";
+  });
+</script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+fn read(log: &str) -> Transcript {
+    Transcript::read(log.as_bytes()).unwrap()
+}
+
+#[cfg(test)]
+fn privmsg(id: u32, login: &str, text: &str) -> String {
+    format!(
+        "@badge-info=;badges=moderator/1;color=#FF0000;display-name={login};emotes=;flags=;id={id};\
+         mod=1;room-id=1;subscriber=0;tmi-sent-ts=1676000000000;turbo=0;user-id=2;user-type= \
+         :{login}!{login}@{login}.tmi.twitch.tv PRIVMSG #bread :{text}\n"
+    )
+}
+
+#[test]
+fn moderation_removes_messages() {
+    let log = [
+        privmsg(1, "crumb", "hello"),
+        privmsg(2, "crumb", "spam"),
+        privmsg(3, "bread", "hi"),
+        "@room-id=1;target-msg-id=1;login=crumb;tmi-sent-ts=1676000001000 \
+         :tmi.twitch.tv CLEARMSG #bread :hello\n"
+            .to_string(),
+        "@room-id=1;target-user-id=2;ban-duration=600;tmi-sent-ts=1676000002000 \
+         :tmi.twitch.tv CLEARCHAT #bread :crumb\n"
+            .to_string(),
+        privmsg(4, "crumb", "back"),
+    ]
+    .concat();
+    let transcript = read(&log);
+    assert_eq!(transcript.channel.as_deref(), Some("bread"));
+    let removed: Vec<_> = transcript
+        .entries
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::Chat { msg, removed } => Some((msg.message_text.as_str(), *removed)),
+            Entry::Event { .. } => None,
+        })
+        .collect();
+    assert_eq!(
+        removed,
+        [
+            ("hello", true),
+            ("spam", true),
+            ("hi", false),
+            ("back", false)
+        ]
+    );
+    assert!(matches!(
+        &transcript.entries[3],
+        Entry::Event { text, .. } if text == "crumb was timed out for 10m"
+    ));
+}
+
+#[test]
+fn html_is_escaped_and_styled() {
+    let log = [
+        privmsg(1, "crumb", "<script>alert(1)</script> & more"),
+        "@room-id=1;target-user-id=2;tmi-sent-ts=1676000002000 \
+         :tmi.twitch.tv CLEARCHAT #bread :crumb\n"
+            .to_string(),
+    ]
+    .concat();
+    let mut page = vec![];
+    write_html(&read(&log), &mut page).unwrap();
+    let page = String::from_utf8(page).unwrap();
+    assert!(page.starts_with("<!DOCTYPE html>"));
+    assert!(page.contains("<title>#bread chat</title>"));
+    assert!(page.contains("&lt;script&gt;alert(1)&lt;/script&gt; &amp; more"));
+    assert!(!page.contains("<script>alert"));
+    assert!(page.contains("<h2 class=\"day\">2023-02-10</h2>"));
+    assert!(page.contains("<div class=\"line chat removed\"><time datetime=\"2023-02-10T03:33:20+00:00\">03:33:20</time>"));
+    assert!(page.contains("<span class=\"badges\">🗡️</span><span class=\"name\" style=\"color: #FF0000\">crumb</span>"));
+    assert!(page.contains("* crumb was banned"));
+}
+
+#[test]
+fn the_sample_log_exports() {
+    let log = std::fs::read("tests/irc_data_no_ping").unwrap();
+    let transcript = Transcript::read(log.as_slice()).unwrap();
+    assert_eq!(transcript.channel.as_deref(), Some("harukakaribu"));
+    let mut page = vec![];
+    write_html(&transcript, &mut page).unwrap();
+    let page = String::from_utf8(page).unwrap();
+    let chat = page.matches("class=\"line chat").count();
+    assert!(chat > 10, "{page}");
+}
+
+#[test]
+fn filters_by_time_and_user() {
+    let log = [
+        privmsg(1, "crumb", "hello"),
+        privmsg(2, "bread", "hi").replace("1676000000000", "1676000060000"),
+        "@room-id=1;target-user-id=2;tmi-sent-ts=1676000120000 \
+         :tmi.twitch.tv CLEARCHAT #bread :crumb\n"
+            .to_string(),
+    ]
+    .concat();
+    let users = |filter: Filter| {
+        let mut transcript = read(&log);
+        transcript.retain(&filter);
+        let users = transcript
+            .entries
+            .iter()
+            .map(|e| e.user().unwrap().to_string());
+        users.collect::<Vec<_>>()
+    };
+    let from = |s: &str| Some(s.parse::<TimeBound>().unwrap());
+    assert_eq!(
+        users(Filter {
+            users: vec!["CRUMB".to_string()],
+            ..Filter::default()
+        }),
+        ["crumb", "crumb"]
+    );
+    assert_eq!(
+        users(Filter {
+            from: from("00:01"),
+            to: from("2023-02-10T03:35:00Z"),
+            ..Filter::default()
+        }),
+        ["bread"]
+    );
+    assert!("1:60".parse::<TimeBound>().is_err());
+    assert_eq!(
+        "1:02:03".parse(),
+        Ok(TimeBound::After(Duration::seconds(3723)))
+    );
+}
+
+#[test]
+fn text_and_markdown_are_plain() {
+    let log = [
+        privmsg(1, "crumb", "*hi* <3"),
+        "@room-id=1;target-user-id=2;tmi-sent-ts=1676000002000 \
+         :tmi.twitch.tv CLEARCHAT #bread :crumb\n"
+            .to_string(),
+    ]
+    .concat();
+    let transcript = read(&log);
+    let mut text = vec![];
+    write_text(&transcript, &mut text).unwrap();
+    assert_eq!(
+        String::from_utf8(text).unwrap(),
+        "00:00:00 🗡️crumb: *hi* <3 [removed]\n00:00:02 * crumb was banned\n"
+    );
+    let mut markdown = vec![];
+    write_markdown(&transcript, &mut markdown).unwrap();
+    assert_eq!(
+        String::from_utf8(markdown).unwrap(),
+        "# #bread chat\n\n\
+         - `00:00:00` ~~🗡️**crumb**: \\*hi\\* \\<3~~\n\
+         - `00:00:02` *crumb was banned*\n"
+    );
+}
@@ -0,0 +1,307 @@
+//! The input line of the authenticated mode
+//!
+//! Every line typed is sent to the channel, except for these commands:
+//! * `/me <text>` sends an action.
+//! * `/reply <user or message id> <text>` replies to a message, by name the
+//!   last message of that user.
+//! * `//<text>` sends `/<text>` as is.
+//!
+//! Moderators also have the commands in [crate::moderation].
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io::{self, prelude::*};
+use std::sync::{Arc, Mutex};
+use twitch_irc::message::ServerMessage;
+
+use crate::error::{Error, Result};
+use crate::moderation::{confirmed, Action, AuditLog, Helix, ModCommand};
+use crate::queue::{self, Policy};
+use crate::setup::TwitchClient;
+use crate::sink::Sink;
+
+/// What a line of input does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Say(String),
+    Me(String),
+    /// `to` is a message id, or a user whose last message is replied to.
+    Reply {
+        to: String,
+        text: String,
+    },
+    Moderate(ModCommand),
+}
+
+impl Command {
+    /// `None` for a blank line, an error for an unknown command.
+    pub fn parse(line: &str) -> std::result::Result<Option<Command>, String> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        if let Some(escaped) = line.strip_prefix("//") {
+            return Ok(Some(Command::Say(format!("/{escaped}"))));
+        }
+        let Some(command) = line.strip_prefix('/') else {
+            return Ok(Some(Command::Say(line.to_string())));
+        };
+        let (name, rest) = command.split_once(' ').unwrap_or((command, ""));
+        let rest = rest.trim();
+        if let Some(command) = ModCommand::parse(name, rest) {
+            return command.map(|command| Some(Command::Moderate(command)));
+        }
+        match name {
+            "me" if !rest.is_empty() => Ok(Some(Command::Me(rest.to_string()))),
+            "reply" => match rest.split_once(' ') {
+                Some((to, text)) if !text.trim().is_empty() => Ok(Some(Command::Reply {
+                    to: to.to_string(),
+                    text: text.trim().to_string(),
+                })),
+                _ => Err("usage: /reply <user or message id> <text>".to_string()),
+            },
+            "me" => Err("usage: /me <text>".to_string()),
+            _ => Err(format!(
+                "unknown command /{name}, use // to send it as text"
+            )),
+        }
+    }
+}
+
+/// The last message id of every chatter, so replies can go by name.
+#[derive(Clone, Default)]
+pub struct RecentMessages(Arc<Mutex<HashMap<String, String>>>);
+
+impl RecentMessages {
+    /// `target` itself if it looks like a message id, else the last
+    /// message of the user `target`.
+    pub fn message_id(&self, target: &str) -> Option<String> {
+        // Message ids are UUIDs, logins can't contain a dash
+        if target.contains('-') {
+            return Some(target.to_string());
+        }
+        let login = target.trim_start_matches('@').to_lowercase();
+        self.0.lock().unwrap().get(&login).cloned()
+    }
+}
+
+#[async_trait]
+impl Sink for RecentMessages {
+    async fn send(&mut self, message: ServerMessage) -> Result<bool> {
+        if let ServerMessage::Privmsg(msg) = message {
+            let mut recent = self.0.lock().unwrap();
+            recent.insert(msg.sender.login, msg.message_id);
+        }
+        Ok(true)
+    }
+
+    async fn finish(&mut self) -> Result<u64> {
+        Ok(0)
+    }
+}
+
+/// Everything the input line needs to send to a channel.
+pub struct Chat {
+    pub client: TwitchClient,
+    pub channel: String,
+    /// Who is logged in, for the audit log.
+    pub login: String,
+    pub recent: RecentMessages,
+    /// Sends the moderator commands.
+    pub helix: Helix,
+    pub audit_log: AuditLog,
+}
+
+/// Sends every line of `input` to the channel until the input ends.
+///
+/// Moderator commands are only sent after the next line confirms them.
+/// Lines that can't be sent are reported on stderr & skipped, only failing
+/// to read `input` is an error.
+pub async fn run_input<R>(mut chat: Chat, input: R) -> Result<()>
+where
+    R: Read + Send + 'static,
+{
+    let (tx, mut lines) = queue::channel(16, Policy::Block);
+    // Reads block, keep them off the runtime's worker threads
+    tokio::task::spawn_blocking(move || {
+        for line in io::BufReader::new(input).lines() {
+            if tx.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let mut pending: Option<Action> = None;
+    while let Some(line) = lines.recv().await {
+        let line = line.map_err(Error::Input)?;
+        let res = match pending.take() {
+            Some(action) if confirmed(&line) => chat.moderate(action).await,
+            Some(_) => Err("cancelled".to_string()),
+            None => match Command::parse(&line) {
+                Ok(Some(Command::Moderate(command))) => {
+                    command.resolve(&chat.recent).map(|action| {
+                        eprint!("? {action} in #{}? [y/N] ", chat.channel);
+                        pending = Some(action);
+                    })
+                }
+                Ok(Some(command)) => chat.send(command).await,
+                Ok(None) => Ok(()),
+                Err(err) => Err(err),
+            },
+        };
+        if let Err(err) = res {
+            eprintln!("! {err}");
+        }
+    }
+    Ok(())
+}
+
+impl Chat {
+    async fn send(&self, command: Command) -> std::result::Result<(), String> {
+        let channel = self.channel.clone();
+        match command {
+            Command::Say(text) => self.client.say(channel, text).await,
+            Command::Me(text) => self.client.me(channel, text).await,
+            Command::Reply { to, text } => {
+                let id = self
+                    .recent
+                    .message_id(&to)
+                    .ok_or_else(|| format!("no message from {to} to reply to"))?;
+                self.client.say_in_reply_to(&(channel, id), text).await
+            }
+            Command::Moderate(_) => unreachable!("Moderator commands are confirmed first"),
+        }
+        .map_err(|err| format!("Could not send: {err}"))
+    }
+
+    /// Sends a confirmed moderator action & records twitch's answer in the
+    /// audit log.
+    async fn moderate(&mut self, action: Action) -> std::result::Result<(), String> {
+        let res = self.helix.send(&action).await;
+        let recorded = self
+            .audit_log
+            .record(&self.channel, &self.login, &action, &res);
+        res?;
+        recorded.map_err(|err| format!("{action} was done, but not audited: {err}"))
+    }
+}
+
+#[test]
+fn parse_commands() {
+    let parse = |line| Command::parse(line).unwrap();
+    assert_eq!(parse("  "), None);
+    assert_eq!(parse("hello"), Some(Command::Say("hello".to_string())));
+    assert_eq!(parse("/me waves"), Some(Command::Me("waves".to_string())));
+    assert_eq!(parse("//shrug"), Some(Command::Say("/shrug".to_string())));
+    assert_eq!(
+        parse("/reply @Bread good morning"),
+        Some(Command::Reply {
+            to: "@Bread".to_string(),
+            text: "good morning".to_string()
+        })
+    );
+    assert!(Command::parse("/me").is_err());
+    assert!(Command::parse("/reply bread").is_err());
+    assert!(Command::parse("/raid bread").is_err());
+    assert!(matches!(parse("/ban bread"), Some(Command::Moderate(_))));
+}
+
+#[tokio::test]
+async fn replies_go_to_the_last_message() {
+    let mut recent = RecentMessages::default();
+    assert_eq!(recent.message_id("bread"), None);
+    let privmsg = crate::setup::make_privmsg_example();
+    let login = privmsg.sender.login.clone();
+    recent.send(ServerMessage::Privmsg(privmsg)).await.unwrap();
+    assert_eq!(recent.message_id(&format!("@{login}")).unwrap(), "7");
+    let id = "885196de-cb67-427a-baa8-82f9b0fcd05f";
+    assert_eq!(recent.message_id(id).unwrap(), id);
+}
+
+#[tokio::test]
+async fn sends_to_a_fake_server() {
+    use crate::auth::Credentials;
+    use crate::endpoint::test_endpoint;
+    use crate::moderation::{FakeHelix, Helix};
+    use crate::server::Server;
+
+    let server = Server::bind("127.0.0.1:0").await.unwrap();
+    let _endpoint = test_endpoint(server.endpoint().unwrap()).await;
+    let server = server.play(|_| vec![]);
+    let credentials = Credentials::new("bread", "abc123");
+    let helix = FakeHelix::start().await;
+    let (_, client) =
+        crate::setup::build_irc_client(Some(credentials.clone()), &Default::default());
+    let dir = tempfile::tempdir().unwrap();
+    let audit_path = dir.path().join("audit.log");
+    let mut recent = RecentMessages::default();
+    let privmsg = crate::setup::make_privmsg_example();
+    let author = privmsg.sender.login.clone();
+    recent.send(ServerMessage::Privmsg(privmsg)).await.unwrap();
+    let chat = Chat {
+        client,
+        channel: "bread".to_string(),
+        login: "bread".to_string(),
+        recent,
+        helix: Helix::new(helix.api.clone(), &credentials, "bread"),
+        audit_log: AuditLog::open(&audit_path).unwrap(),
+    };
+
+    let input = format!(
+        "hello\n/me waves\n/reply 885196de-cb67-427a-baa8-82f9b0fcd05f hi\n\
+         /ban crumb spam\nn\n/timeout crumb 10m\ny\n/delete @{author}\nyes\n\
+         /ban banned\ny\n"
+    );
+    run_input(chat, io::Cursor::new(input)).await.unwrap();
+
+    let privmsgs = |received: &[String]| -> Vec<String> {
+        let privmsgs = received.iter().filter(|l| l.contains("PRIVMSG"));
+        privmsgs.cloned().collect()
+    };
+    let mut received = server.received();
+    while privmsgs(&received).len() < 3 {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        received = server.received();
+    }
+    assert!(
+        received.contains(&"PASS oauth:abc123".to_string()),
+        "{received:?}"
+    );
+    assert!(received.contains(&"NICK bread".to_string()), "{received:?}");
+    let privmsgs = privmsgs(&received);
+    assert_eq!(privmsgs[0], "PRIVMSG #bread :. hello");
+    assert_eq!(privmsgs[1], "PRIVMSG #bread :/me waves");
+    assert_eq!(
+        privmsgs[2],
+        "@reply-parent-msg-id=885196de-cb67-427a-baa8-82f9b0fcd05f PRIVMSG #bread :. hi"
+    );
+    // Moderator commands go to Helix, not to chat
+    assert_eq!(privmsgs.len(), 3, "{privmsgs:?}");
+    let requests = helix.requests.lock().unwrap().clone();
+    let moderated: Vec<&String> = requests
+        .iter()
+        .filter(|r| r.contains("moderation"))
+        .collect();
+    // The first ban wasn't confirmed
+    assert_eq!(moderated.len(), 3, "{requests:?}");
+    assert!(
+        moderated[0].contains("\"user_id\":\"id-crumb\""),
+        "{requests:?}"
+    );
+    assert!(
+        moderated[1].contains("DELETE /helix/moderation/chat?"),
+        "{requests:?}"
+    );
+    assert!(moderated[1].ends_with("&message_id=7"), "{requests:?}");
+
+    let audit = std::fs::read_to_string(audit_path).unwrap();
+    let audited: Vec<&str> = audit.lines().collect();
+    assert_eq!(audited.len(), 3, "{audit}");
+    assert!(
+        audited[0].ends_with("\ttimeout crumb for 10m\tdone"),
+        "{audit}"
+    );
+    assert!(audited[1].ends_with("\tdelete message 7\tdone"), "{audit}");
+    assert!(
+        audited[2].ends_with("\tban banned\tfailed: 400 Bad Request, The user is already banned."),
+        "{audit}"
+    );
+}
@@ -1,6 +1,85 @@
-//! This is for testing purposes, please use the binary.
-pub mod args;
-pub mod badges;
-pub mod logging;
-pub mod pretty_print;
-pub mod setup;
+//! The pieces of the twitch chat viewer, for use in your own program.
+//!
+//! A [Viewer] reads chat from a [Source] & fans it out to any number of
+//! [Sink]s. [TerminalSink] prints the chat like the binary does, [LogSink]
+//! writes the irc log the binary can replay with `--from-stdin` & [OverlaySink]
+//! serves the chat as JSON to overlays. Your own outputs only need to
+//! implement [Sink].
+//!
+//! ```no_run
+//! use twitch_ircv::{FlushPolicy, LogSink, Policy, Source, TerminalSink, Viewer};
+//!
+//! # async fn run() -> twitch_ircv::Result<()> {
+//! let (terminal, _room_state) = TerminalSink::new(std::io::stdout(), chrono::Utc::now());
+//! let log = std::fs::File::create("chat.log").unwrap();
+//! let results = Viewer::builder(Source::Channel("bread".to_string()))
+//!     .sink(terminal, Policy::DropOldest)
+//!     .sink(LogSink::new(log, FlushPolicy::EveryMessage), Policy::Block)
+//!     .build()
+//!     .run()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! To print messages somewhere else, [Formatter] builds the same lines
+//! without writing them.
+//!
+//! [UserRegistry] follows chatters through renames by their user id.
+mod activity;
+mod badges;
+mod dedup;
+mod endpoint;
+mod error;
+mod logging;
+mod overlay;
+mod pretty_print;
+mod queue;
+mod registry;
+mod room_state;
+mod sink;
+mod viewer;
+mod websocket;
+
+// The command line's modules, some of the above build on them. Only the
+// binary uses all of them, & checks them for dead code.
+#[cfg(feature = "sqlite")]
+#[allow(dead_code)]
+mod archive;
+#[allow(dead_code)]
+mod args;
+#[allow(dead_code)]
+mod auth;
+#[allow(dead_code)]
+mod export;
+#[allow(dead_code)]
+mod input;
+#[allow(dead_code)]
+mod moderation;
+#[allow(dead_code)]
+mod profile;
+#[allow(dead_code)]
+mod replay;
+#[allow(dead_code)]
+mod search;
+#[allow(dead_code)]
+mod server;
+#[allow(dead_code)]
+mod setup;
+
+pub use args::{DEFAULT_BUFFER_SIZE, DEFAULT_JOIN_TIMEOUT};
+pub use badges::{parse_badges, Badges, ChannelStatus, Subscriber};
+pub use dedup::Dedup;
+pub use endpoint::Pool;
+pub use error::{Error, Result};
+pub use logging::{log_v0, FlushPolicy, LogSink};
+pub use overlay::OverlaySink;
+pub use pretty_print::{
+    is_fatal_notice, message_handler, ChatLine, Formatter, Terminal, TerminalSink,
+    FATAL_NOTICE_IDS, LOGIN_FAILURE_NOTICES,
+};
+pub use queue::Policy;
+pub use registry::{Name, UserRegistry};
+pub use room_state::{ModeChange, RoomState};
+pub use sink::{run_sink, Broadcaster, Sink};
+pub use viewer::{Source, Viewer, ViewerBuilder};
@@ -1,23 +1,108 @@
+use async_trait::async_trait;
+use std::io;
 use std::io::prelude::*;
+use std::time::Duration;
 use twitch_irc::message::AsRawIRC;
 use twitch_irc::message::ServerMessage;
 
+use crate::error::{Error, Result};
+use crate::sink::Sink;
+
+/// When [LogSink] flushes the log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlushPolicy {
+    EveryMessage,
+    /// Flush at most this often, if something was written.
+    Interval(Duration),
+}
+
+impl FlushPolicy {
+    /// `0` seconds flushes every message.
+    pub fn from_secs(secs: u64) -> FlushPolicy {
+        if secs == 0 {
+            FlushPolicy::EveryMessage
+        } else {
+            FlushPolicy::Interval(Duration::from_secs(secs))
+        }
+    }
+}
+
 /// Log messages in IRC format
 ///
-/// Logs PRIVMSG, USERNOTICE, CLEARCHAT, & CLEARMSG.
-pub async fn log_v0<W: Write>(message: ServerMessage, out: &mut W) {
+/// Logs PRIVMSG, USERNOTICE, CLEARCHAT, CLEARMSG, & ROOMSTATE.
+///
+/// Returns whether `message` was one of those & got logged.
+pub fn log_v0<W: Write>(message: ServerMessage, out: &mut W) -> io::Result<bool> {
     match message {
         ServerMessage::Privmsg(msg) => writeln!(out, "{}", msg.source.as_raw_irc()),
         ServerMessage::UserNotice(msg) => writeln!(out, "{}", msg.source.as_raw_irc()),
         ServerMessage::ClearChat(msg) => writeln!(out, "{}", msg.source.as_raw_irc()),
         ServerMessage::ClearMsg(msg) => writeln!(out, "{}", msg.source.as_raw_irc()),
-        _ => Ok(()),
+        ServerMessage::RoomState(msg) => writeln!(out, "{}", msg.source.as_raw_irc()),
+        _ => return Ok(false),
     }
-    .unwrap();
+    .map(|()| true)
 }
 
-#[tokio::test]
-async fn log_v0_privmsg() {
+/// Logs every message with [log_v0].
+///
+/// The log is always flushed in [Sink::finish], so closing the queue is
+/// enough to get everything onto the disk.
+pub struct LogSink<W> {
+    out: W,
+    flush: FlushPolicy,
+    /// Written since the last flush.
+    dirty: bool,
+    logged: u64,
+}
+
+impl<W: Write> LogSink<W> {
+    pub fn new(out: W, flush: FlushPolicy) -> LogSink<W> {
+        LogSink {
+            out,
+            flush,
+            dirty: false,
+            logged: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl<W: Write + Send + 'static> Sink for LogSink<W> {
+    async fn send(&mut self, message: ServerMessage) -> Result<bool> {
+        if log_v0(message, &mut self.out).map_err(Error::LogWrite)? {
+            self.logged += 1;
+            match self.flush {
+                FlushPolicy::EveryMessage => self.out.flush().map_err(Error::LogWrite)?,
+                FlushPolicy::Interval(_) => self.dirty = true,
+            }
+        }
+        Ok(true)
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        match self.flush {
+            FlushPolicy::EveryMessage => None,
+            FlushPolicy::Interval(period) => Some(period),
+        }
+    }
+
+    async fn tick(&mut self) -> Result<()> {
+        if std::mem::take(&mut self.dirty) {
+            self.out.flush().map_err(Error::LogWrite)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of messages logged.
+    async fn finish(&mut self) -> Result<u64> {
+        self.out.flush().map_err(Error::LogWrite)?;
+        Ok(self.logged)
+    }
+}
+
+#[test]
+fn log_v0_privmsg() {
     use twitch_irc::irc;
     use twitch_irc::message::PrivmsgMessage;
 
@@ -29,8 +114,128 @@ async fn log_v0_privmsg() {
     let fake_privmsg = ServerMessage::Privmsg(fake_privmsg);
 
     let mut output = vec![];
-    log_v0(fake_privmsg, &mut output).await;
+    log_v0(fake_privmsg, &mut output).unwrap();
     let output = String::from_utf8(output).unwrap();
 
     assert_eq!(output, expected);
 }
+
+/// Shared buffer, so it can be checked while the logger still holds it
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct SharedBuf(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+#[cfg(test)]
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl SharedBuf {
+    fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+fn privmsg() -> ServerMessage {
+    ServerMessage::Privmsg(crate::setup::make_privmsg_example())
+}
+
+#[tokio::test]
+async fn flushes_when_channel_closes() {
+    use crate::queue::{self, Policy};
+    use crate::sink::run_sink;
+
+    let shared = SharedBuf::default();
+    let (tx, rx) = queue::channel(16, Policy::Block);
+    tx.send(privmsg()).await.unwrap();
+    tx.send(privmsg()).await.unwrap();
+    drop(tx);
+
+    let policy = FlushPolicy::Interval(Duration::from_secs(3600));
+    let mut sink = LogSink::new(io::BufWriter::new(shared.clone()), policy);
+    let logged = run_sink(rx, &mut sink).await.unwrap();
+    assert_eq!(logged, 2);
+    // The BufWriter is still alive, everything must have been flushed anyway
+    let expected = format!("{}\n", crate::setup::PRIVMSG_EXAMPLE).len() * 2;
+    assert_eq!(shared.len(), expected);
+}
+
+#[tokio::test]
+async fn flushes_every_message() {
+    use crate::queue::{self, Policy};
+    use crate::sink::run_sink;
+
+    let shared = SharedBuf::default();
+    let (tx, rx) = queue::channel(16, Policy::Block);
+    let mut sink = LogSink::new(
+        io::BufWriter::new(shared.clone()),
+        FlushPolicy::EveryMessage,
+    );
+    let logger = tokio::spawn(async move { run_sink(rx, &mut sink).await });
+
+    tx.send(privmsg()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_ne!(shared.len(), 0);
+
+    drop(tx);
+    logger.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn propagates_flush_errors() {
+    use crate::queue::{self, Policy};
+    use crate::sink::run_sink;
+
+    /// Fails every write, which the BufWriter only makes when flushed
+    struct FailsWrites;
+    impl Write for FailsWrites {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::Other.into())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let (tx, rx) = queue::channel(16, Policy::Block);
+    tx.send(privmsg()).await.unwrap();
+    drop(tx);
+    // The BufWriter only writes through when flushed
+    let policy = FlushPolicy::Interval(Duration::from_secs(3600));
+    let mut sink = LogSink::new(io::BufWriter::new(FailsWrites), policy);
+    let res = run_sink(rx, &mut sink).await;
+    assert!(
+        matches!(res, Err(Error::LogWrite(_))),
+        "The final flush must not be silently dropped"
+    );
+}
+
+#[tokio::test]
+async fn propagates_write_errors() {
+    use crate::queue::{self, Policy};
+    use crate::sink::run_sink;
+
+    struct Full;
+    impl Write for Full {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let (tx, rx) = queue::channel(16, Policy::Block);
+    tx.send(privmsg()).await.unwrap();
+    let res = run_sink(rx, &mut LogSink::new(Full, FlushPolicy::EveryMessage)).await;
+    match res {
+        Err(Error::LogWrite(err)) => assert_eq!(err.to_string(), "disk full"),
+        res => panic!("Expected a write error, got {res:?}"),
+    }
+}
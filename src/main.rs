@@ -1,13 +1,57 @@
+mod activity;
+#[cfg(feature = "sqlite")]
+mod archive;
 mod args;
+mod auth;
 mod badges;
+mod dedup;
+mod endpoint;
+mod error;
+mod export;
+mod input;
 mod logging;
+mod moderation;
+mod overlay;
 mod pretty_print;
+mod profile;
+mod queue;
+mod registry;
+mod replay;
+mod room_state;
+mod search;
+// Tests script it in more ways than the replay does
+#[allow(dead_code)]
+mod server;
 mod setup;
+mod sink;
+mod websocket;
 
 use std::io::{stdin, stdout};
 
+use crate::pretty_print::Terminal;
+
 #[tokio::main]
 async fn main() {
-    let args: args::Args = argh::from_env();
-    setup::init(args, stdin(), stdout()).await;
+    let mut args = args::Args::from_env();
+    let res = match args.command.take() {
+        Some(args::Command::Serve(serve)) => replay::serve(serve).await,
+        Some(args::Command::Export(export)) => export::export(export),
+        Some(args::Command::Search(search)) => search::search(search),
+        Some(args::Command::User(user)) => profile::user(user),
+        #[cfg(feature = "sqlite")]
+        Some(args::Command::Import(import)) => archive::import(import),
+        #[cfg(feature = "sqlite")]
+        Some(args::Command::Query(query)) => archive::query(query),
+        None => setup::init(args, stdin(), stdout(), Terminal::stdout()).await,
+    };
+    let code = match res {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("error: {err}");
+            err.exit_code()
+        }
+    };
+    // Exit right away, after a shutdown the stdin reader may still be
+    // blocked on a read the runtime would wait for.
+    std::process::exit(code.into())
 }
@@ -0,0 +1,618 @@
+//! Moderator commands of the input line & their audit log
+//!
+//! * `/timeout <user> <duration> [reason]`, the duration like `600`, `10m`,
+//!   `1h` or `1d`.
+//! * `/ban <user> [reason]`
+//! * `/delete <user or message id>`, by name the last message of that user.
+//!
+//! Every command asks for confirmation first. Confirmed ones are sent
+//! through the Helix API, twitch no longer takes them as chat messages, &
+//! written to the audit log with twitch's answer.
+use chrono::{SecondsFormat, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::auth::Credentials;
+use crate::input::RecentMessages;
+use crate::room_state::short_duration;
+
+/// A moderator command as typed, see [Action] for one ready to send.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModCommand {
+    Timeout {
+        user: String,
+        duration: Duration,
+        reason: Option<String>,
+    },
+    Ban {
+        user: String,
+        reason: Option<String>,
+    },
+    /// `target` is a message id, or a user whose last message is deleted.
+    Delete { target: String },
+}
+
+impl ModCommand {
+    /// `None` if `name` isn't a moderator command.
+    pub fn parse(name: &str, args: &str) -> Option<Result<ModCommand, String>> {
+        let mut words = args.split_whitespace();
+        let mut user = || {
+            words
+                .next()
+                .map(|user| user.trim_start_matches('@').to_lowercase())
+        };
+        let command = match name {
+            "timeout" => {
+                let usage = "usage: /timeout <user> <duration> [reason]";
+                let (Some(user), Some(duration)) = (user(), words.next()) else {
+                    return Some(Err(usage.to_string()));
+                };
+                let Some(duration) = parse_duration(duration) else {
+                    return Some(Err(format!("{duration} is not a duration, try 10m")));
+                };
+                ModCommand::Timeout {
+                    user,
+                    duration,
+                    reason: rest(words),
+                }
+            }
+            "ban" => match user() {
+                Some(user) => ModCommand::Ban {
+                    user,
+                    reason: rest(words),
+                },
+                None => return Some(Err("usage: /ban <user> [reason]".to_string())),
+            },
+            "delete" => match words.next() {
+                Some(target) => ModCommand::Delete {
+                    target: target.to_string(),
+                },
+                None => return Some(Err("usage: /delete <user or message id>".to_string())),
+            },
+            _ => return None,
+        };
+        Some(Ok(command))
+    }
+
+    /// Looks up the message a delete by name is for.
+    pub fn resolve(self, recent: &RecentMessages) -> Result<Action, String> {
+        Ok(match self {
+            ModCommand::Timeout {
+                user,
+                duration,
+                reason,
+            } => Action::Timeout {
+                user,
+                duration,
+                reason,
+            },
+            ModCommand::Ban { user, reason } => Action::Ban { user, reason },
+            ModCommand::Delete { target } => Action::Delete {
+                message_id: recent
+                    .message_id(&target)
+                    .ok_or_else(|| format!("no message from {target} to delete"))?,
+            },
+        })
+    }
+}
+
+fn rest<'a>(words: impl Iterator<Item = &'a str>) -> Option<String> {
+    let rest = words.collect::<Vec<_>>().join(" ");
+    (!rest.is_empty()).then_some(rest)
+}
+
+/// Parses `600`, `600s`, `10m`, `1h` or `1d`.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => text.split_at(i),
+        None => (text, "s"),
+    };
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    let number: u64 = number.parse().ok()?;
+    Some(Duration::from_secs(number.checked_mul(secs)?)).filter(|d| !d.is_zero())
+}
+
+/// A moderator command with its target resolved, ready to confirm & send.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Timeout {
+        user: String,
+        duration: Duration,
+        reason: Option<String>,
+    },
+    Ban {
+        user: String,
+        reason: Option<String>,
+    },
+    Delete {
+        message_id: String,
+    },
+}
+
+/// Reads like the confirmation prompt, `ban bread (spam)`.
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let because = |reason: &Option<String>| match reason {
+            Some(reason) => format!(" ({reason})"),
+            None => String::new(),
+        };
+        match self {
+            Action::Timeout {
+                user,
+                duration,
+                reason,
+            } => write!(
+                f,
+                "timeout {user} for {}{}",
+                short_duration(duration),
+                because(reason)
+            ),
+            Action::Ban { user, reason } => write!(f, "ban {user}{}", because(reason)),
+            Action::Delete { message_id } => write!(f, "delete message {message_id}"),
+        }
+    }
+}
+
+/// Where the Helix API is, twitch's by default.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Api {
+    /// Tells the client id, user id & scopes of a token.
+    pub validate: String,
+    /// The root of the Helix endpoints.
+    pub helix: String,
+}
+
+impl Default for Api {
+    fn default() -> Api {
+        Api {
+            validate: "https://id.twitch.tv/oauth2/validate".to_string(),
+            helix: "https://api.twitch.tv/helix".to_string(),
+        }
+    }
+}
+
+/// The scope a token needs to timeout & ban.
+pub const BAN_SCOPE: &str = "moderator:manage:banned_users";
+/// The scope a token needs to delete messages.
+pub const DELETE_SCOPE: &str = "moderator:manage:chat_messages";
+
+/// Sends [Action]s to a channel through the Helix API.
+///
+/// The client id of the token & the ids of the moderator & broadcaster are
+/// looked up before the first action.
+pub struct Helix {
+    http: reqwest::Client,
+    api: Api,
+    token: String,
+    channel: String,
+    session: Option<Session>,
+}
+
+/// What the token & the channel name stand for.
+struct Session {
+    client_id: String,
+    moderator_id: String,
+    broadcaster_id: String,
+    scopes: Vec<String>,
+}
+
+impl Helix {
+    /// Acts in `channel` as the user of `credentials`.
+    pub fn new(api: Api, credentials: &Credentials, channel: &str) -> Helix {
+        Helix {
+            http: reqwest::Client::new(),
+            api,
+            token: credentials.token().to_string(),
+            channel: channel.to_string(),
+            session: None,
+        }
+    }
+
+    /// Sends `action`, `Err` with twitch's reason if it wasn't done.
+    pub async fn send(&mut self, action: &Action) -> Result<(), String> {
+        if self.session.is_none() {
+            self.session = Some(self.start().await?);
+        }
+        let session = self.session.as_ref().expect("Started above");
+        let ids = [
+            ("broadcaster_id", session.broadcaster_id.as_str()),
+            ("moderator_id", session.moderator_id.as_str()),
+        ];
+        let (user, duration, reason) = match action {
+            Action::Timeout {
+                user,
+                duration,
+                reason,
+            } => (user, Some(duration), reason),
+            Action::Ban { user, reason } => (user, None, reason),
+            Action::Delete { message_id } => {
+                session.require(DELETE_SCOPE)?;
+                let url = format!("{}/moderation/chat", self.api.helix);
+                let request = self.helix(session, self.http.delete(url));
+                answer(request.query(&ids).query(&[("message_id", message_id)])).await?;
+                return Ok(());
+            }
+        };
+        session.require(BAN_SCOPE)?;
+        let mut data = json!({ "user_id": self.user_id(session, user).await? });
+        if let Some(duration) = duration {
+            data["duration"] = duration.as_secs().into();
+        }
+        if let Some(reason) = reason {
+            data["reason"] = reason.as_str().into();
+        }
+        let url = format!("{}/moderation/bans", self.api.helix);
+        let request = self.helix(session, self.http.post(url)).query(&ids);
+        answer(request.json(&json!({ "data": data }))).await?;
+        Ok(())
+    }
+
+    /// Validates the token & looks up the channel.
+    async fn start(&self) -> Result<Session, String> {
+        #[derive(Deserialize)]
+        struct Validation {
+            client_id: String,
+            user_id: String,
+            scopes: Vec<String>,
+        }
+        let request = self
+            .http
+            .get(&self.api.validate)
+            .header("Authorization", format!("OAuth {}", self.token));
+        let validation: Validation = parse(&answer(request).await?)?;
+        let mut session = Session {
+            client_id: validation.client_id,
+            moderator_id: validation.user_id,
+            broadcaster_id: String::new(),
+            scopes: validation.scopes,
+        };
+        session.broadcaster_id = self.user_id(&session, &self.channel).await?;
+        Ok(session)
+    }
+
+    /// The user id of `login`.
+    async fn user_id(&self, session: &Session, login: &str) -> Result<String, String> {
+        #[derive(Deserialize)]
+        struct Users {
+            data: Vec<User>,
+        }
+        #[derive(Deserialize)]
+        struct User {
+            id: String,
+        }
+        let url = format!("{}/users", self.api.helix);
+        let request = self.helix(session, self.http.get(url));
+        let users: Users = parse(&answer(request.query(&[("login", login)])).await?)?;
+        match users.data.into_iter().next() {
+            Some(user) => Ok(user.id),
+            None => Err(format!("no user {login}")),
+        }
+    }
+
+    /// `request` with the headers every Helix endpoint wants.
+    fn helix(
+        &self,
+        session: &Session,
+        request: reqwest::RequestBuilder,
+    ) -> reqwest::RequestBuilder {
+        request
+            .bearer_auth(&self.token)
+            .header("Client-Id", &session.client_id)
+    }
+}
+
+impl Session {
+    fn require(&self, scope: &str) -> Result<(), String> {
+        match self.scopes.iter().any(|granted| granted == scope) {
+            true => Ok(()),
+            false => Err(format!("the token lacks the {scope} scope")),
+        }
+    }
+}
+
+/// The body of a successful response, else the status & twitch's message.
+async fn answer(request: reqwest::RequestBuilder) -> Result<String, String> {
+    #[derive(Deserialize)]
+    struct Problem {
+        message: String,
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|err| format!("Could not reach twitch: {err}"))?;
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|err| format!("Could not read twitch's answer: {err}"))?;
+    if status.is_success() {
+        return Ok(body);
+    }
+    match serde_json::from_str::<Problem>(&body) {
+        Ok(problem) => Err(format!("{status}, {}", problem.message)),
+        Err(_) => Err(status.to_string()),
+    }
+}
+
+fn parse<'a, T: Deserialize<'a>>(body: &'a str) -> Result<T, String> {
+    serde_json::from_str(body).map_err(|err| format!("Unexpected answer from twitch: {err}"))
+}
+
+/// Whether an answer to a confirmation prompt is a yes.
+pub fn confirmed(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Tab separated record of every moderator action sent, & whether twitch
+/// did it.
+///
+/// `time  #channel  moderator  action  result`
+pub struct AuditLog {
+    file: File,
+}
+
+impl AuditLog {
+    /// Appends to `path`, creating it & its directory if needed.
+    pub fn open(path: &Path) -> io::Result<AuditLog> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog { file })
+    }
+
+    pub fn record(
+        &mut self,
+        channel: &str,
+        moderator: &str,
+        action: &Action,
+        result: &Result<(), String>,
+    ) -> io::Result<()> {
+        let result = match result {
+            Ok(()) => "done".to_string(),
+            Err(err) => format!("failed: {err}"),
+        };
+        writeln!(
+            self.file,
+            "{}\t#{channel}\t{moderator}\t{action}\t{result}",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+        )
+    }
+}
+
+/// `$XDG_DATA_HOME/twitch-ircv/audit.log`, or the same in `~/.local/share`.
+pub fn default_audit_log() -> Option<PathBuf> {
+    let data = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(data.join("twitch-ircv").join("audit.log"))
+}
+
+#[test]
+fn parse_mod_commands() {
+    let parse = |name, args| ModCommand::parse(name, args).unwrap();
+    assert_eq!(
+        parse("timeout", "@Bread 10m stop spamming"),
+        Ok(ModCommand::Timeout {
+            user: "bread".to_string(),
+            duration: Duration::from_secs(600),
+            reason: Some("stop spamming".to_string()),
+        })
+    );
+    assert_eq!(
+        parse("ban", "bread"),
+        Ok(ModCommand::Ban {
+            user: "bread".to_string(),
+            reason: None
+        })
+    );
+    assert!(parse("timeout", "bread").is_err());
+    assert!(parse("timeout", "bread 10y").is_err());
+    assert!(parse("delete", "").is_err());
+    assert!(ModCommand::parse("me", "waves").is_none());
+}
+
+#[test]
+fn parse_durations() {
+    assert_eq!(parse_duration("600"), Some(Duration::from_secs(600)));
+    assert_eq!(parse_duration("10m"), Some(Duration::from_secs(600)));
+    assert_eq!(parse_duration("1d"), Some(Duration::from_secs(86400)));
+    assert_eq!(parse_duration("0"), None);
+    assert_eq!(parse_duration("m"), None);
+    assert_eq!(parse_duration("1w"), None);
+}
+
+#[test]
+fn actions_read_like_prompts() {
+    let timeout = Action::Timeout {
+        user: "bread".to_string(),
+        duration: Duration::from_secs(600),
+        reason: Some("spam".to_string()),
+    };
+    assert_eq!(timeout.to_string(), "timeout bread for 10m (spam)");
+    let delete = Action::Delete {
+        message_id: "7".to_string(),
+    };
+    assert_eq!(delete.to_string(), "delete message 7");
+    assert!(confirmed(" Yes\n"));
+    assert!(!confirmed(""));
+}
+
+#[test]
+fn audit_log_appends() -> io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("nested").join("audit.log");
+    let ban = Action::Ban {
+        user: "bread".to_string(),
+        reason: None,
+    };
+    AuditLog::open(&path)?.record("bread", "mod", &ban, &Ok(()))?;
+    AuditLog::open(&path)?.record("bread", "mod", &ban, &Err("offline".to_string()))?;
+    let log = std::fs::read_to_string(&path)?;
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(
+        lines[0].ends_with("\t#bread\tmod\tban bread\tdone"),
+        "{log}"
+    );
+    assert!(lines[1].ends_with("\tfailed: offline"), "{log}");
+    Ok(())
+}
+
+/// Answers like the Helix API, for [Helix] to talk to in tests.
+///
+/// The token has both moderator scopes, every user id is `id-` & the login,
+/// & `banned` can't be banned again.
+#[cfg(test)]
+pub(crate) struct FakeHelix {
+    pub api: Api,
+    /// `METHOD /path?query body` of every request, with the headers that
+    /// matter in front of it, e.g. `[cid Bearer abc123] GET /helix/users?...`.
+    pub requests: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+}
+
+#[cfg(test)]
+impl FakeHelix {
+    pub async fn start() -> FakeHelix {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let root = format!("http://{}", listener.local_addr().unwrap());
+        let requests = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut stream = BufReader::new(stream);
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                let request = line.split(' ').take(2).collect::<Vec<_>>().join(" ");
+                let (mut length, mut client_id, mut auth) = (0, String::new(), String::new());
+                loop {
+                    line.clear();
+                    stream.read_line(&mut line).await.unwrap();
+                    let Some((name, value)) = line.trim_end().split_once(": ") else {
+                        break;
+                    };
+                    match name.to_lowercase().as_str() {
+                        "content-length" => length = value.parse().unwrap(),
+                        "client-id" => client_id = value.to_string(),
+                        "authorization" => auth = value.to_string(),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).await.unwrap();
+                let body = String::from_utf8(body).unwrap();
+                let (status, answer) = FakeHelix::answer(&request, &body);
+                let request = format!("[{client_id} {auth}] {request} {body}");
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push(request.trim_end().to_string());
+                let response = format!(
+                    "HTTP/1.1 {status}\r\ncontent-type: application/json\r\n\
+                     content-length: {}\r\nconnection: close\r\n\r\n{answer}",
+                    answer.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        let api = Api {
+            validate: format!("{root}/oauth2/validate"),
+            helix: format!("{root}/helix"),
+        };
+        FakeHelix { api, requests }
+    }
+
+    fn answer(request: &str, body: &str) -> (&'static str, String) {
+        if request.starts_with("GET /oauth2/validate") {
+            let scopes = [BAN_SCOPE, DELETE_SCOPE];
+            let validation = json!({"client_id": "cid", "user_id": "id-mod", "scopes": scopes});
+            return ("200 OK", validation.to_string());
+        }
+        if let Some(login) = request.strip_prefix("GET /helix/users?login=") {
+            return (
+                "200 OK",
+                json!({"data": [{"id": format!("id-{login}")}]}).to_string(),
+            );
+        }
+        if body.contains("id-banned") {
+            let problem = json!({"status": 400, "message": "The user is already banned."});
+            return ("400 Bad Request", problem.to_string());
+        }
+        ("200 OK", r#"{"data": []}"#.to_string())
+    }
+}
+
+#[tokio::test]
+async fn helix_moderates() {
+    let helix = FakeHelix::start().await;
+    let credentials = Credentials::new("mod", "oauth:abc123");
+    let mut client = Helix::new(helix.api.clone(), &credentials, "bread");
+    let timeout = Action::Timeout {
+        user: "crumb".to_string(),
+        duration: Duration::from_secs(600),
+        reason: Some("spam".to_string()),
+    };
+    assert_eq!(client.send(&timeout).await, Ok(()));
+    let ban = Action::Ban {
+        user: "banned".to_string(),
+        reason: None,
+    };
+    assert_eq!(
+        client.send(&ban).await,
+        Err("400 Bad Request, The user is already banned.".to_string())
+    );
+    let delete = Action::Delete {
+        message_id: "7".to_string(),
+    };
+    assert_eq!(client.send(&delete).await, Ok(()));
+
+    let requests = helix.requests.lock().unwrap().clone();
+    let ids = "broadcaster_id=id-bread&moderator_id=id-mod";
+    assert_eq!(
+        requests,
+        [
+            "[ OAuth abc123] GET /oauth2/validate",
+            "[cid Bearer abc123] GET /helix/users?login=bread",
+            "[cid Bearer abc123] GET /helix/users?login=crumb",
+            &format!(
+                "[cid Bearer abc123] POST /helix/moderation/bans?{ids} \
+                 {{\"data\":{{\"duration\":600,\"reason\":\"spam\",\"user_id\":\"id-crumb\"}}}}"
+            ),
+            "[cid Bearer abc123] GET /helix/users?login=banned",
+            &format!(
+                "[cid Bearer abc123] POST /helix/moderation/bans?{ids} \
+                 {{\"data\":{{\"user_id\":\"id-banned\"}}}}"
+            ),
+            &format!("[cid Bearer abc123] DELETE /helix/moderation/chat?{ids}&message_id=7"),
+        ]
+    );
+}
+
+#[test]
+fn helix_needs_the_scopes() {
+    let session = Session {
+        client_id: "cid".to_string(),
+        moderator_id: "id-mod".to_string(),
+        broadcaster_id: "id-bread".to_string(),
+        scopes: vec![DELETE_SCOPE.to_string()],
+    };
+    assert_eq!(session.require(DELETE_SCOPE), Ok(()));
+    assert_eq!(
+        session.require(BAN_SCOPE),
+        Err(format!("the token lacks the {BAN_SCOPE} scope"))
+    );
+}
@@ -0,0 +1,240 @@
+//! Live chat as JSON over WebSocket, for stream overlays & dashboards
+//!
+//! Every client connected to an [OverlaySink] gets one [Event] per text
+//! message. `overlay/chat.html` is a sample overlay using it.
+use async_trait::async_trait;
+use async_tungstenite::tungstenite::Message;
+use futures_util::SinkExt;
+use serde::Serialize;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use twitch_irc::message::{ClearChatAction, RGBColor, ServerMessage};
+
+use crate::badges::{parse_badges, Badges};
+use crate::error::Result;
+use crate::sink::Sink;
+
+/// What overlays are told, tagged by `type`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Chat {
+        id: String,
+        channel: String,
+        user_id: String,
+        login: String,
+        name: String,
+        /// `#RRGGBB`, `null` if the user never picked one.
+        color: Option<String>,
+        badges: Badges,
+        text: String,
+        /// Sent with `/me`.
+        action: bool,
+        /// RFC 3339.
+        sent_at: String,
+    },
+    /// A moderator deleted a message.
+    Delete { id: String },
+    /// A user was timed out or banned, their messages should go.
+    ClearUser {
+        login: String,
+        /// `None` for a ban.
+        timeout_secs: Option<u64>,
+    },
+    /// The whole chat was cleared.
+    Clear,
+}
+
+impl Event {
+    /// `None` for messages overlays don't care about.
+    pub fn from_message(message: &ServerMessage) -> Option<Event> {
+        Some(match message {
+            ServerMessage::Privmsg(msg) => Event::Chat {
+                id: msg.message_id.clone(),
+                channel: msg.channel_login.clone(),
+                user_id: msg.sender.id.clone(),
+                login: msg.sender.login.clone(),
+                name: msg.sender.name.clone(),
+                color: msg.name_color.map(hex_color),
+                badges: parse_badges(&msg.badges),
+                text: msg.message_text.clone(),
+                action: msg.is_action,
+                sent_at: msg.server_timestamp.to_rfc3339(),
+            },
+            ServerMessage::ClearMsg(msg) => Event::Delete {
+                id: msg.message_id.clone(),
+            },
+            ServerMessage::ClearChat(msg) => match &msg.action {
+                ClearChatAction::ChatCleared => Event::Clear,
+                ClearChatAction::UserBanned { user_login, .. } => Event::ClearUser {
+                    login: user_login.clone(),
+                    timeout_secs: None,
+                },
+                ClearChatAction::UserTimedOut {
+                    user_login,
+                    timeout_length,
+                    ..
+                } => Event::ClearUser {
+                    login: user_login.clone(),
+                    timeout_secs: Some(timeout_length.as_secs()),
+                },
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// `#RRGGBB`, like twitch sends it.
+pub(crate) fn hex_color(color: RGBColor) -> String {
+    format!("#{:02X}{:02X}{:02X}", color.r, color.g, color.b)
+}
+
+/// Serves the chat as JSON events to any number of WebSocket clients.
+///
+/// Clients that fall behind skip what they missed. Stops serving once
+/// dropped.
+pub struct OverlaySink {
+    events: broadcast::Sender<String>,
+    local_addr: SocketAddr,
+    server: JoinHandle<()>,
+    sent: u64,
+}
+
+impl OverlaySink {
+    /// Listens on `addr`, each client may fall `capacity` events behind.
+    ///
+    /// Must be called within a tokio runtime.
+    pub fn bind<A: ToSocketAddrs>(addr: A, capacity: usize) -> io::Result<OverlaySink> {
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        let local_addr = listener.local_addr()?;
+        let (events, _) = broadcast::channel(capacity);
+        let server = tokio::spawn(serve(listener, events.clone()));
+        Ok(OverlaySink {
+            events,
+            local_addr,
+            server,
+            sent: 0,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for OverlaySink {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+#[async_trait]
+impl Sink for OverlaySink {
+    async fn send(&mut self, message: ServerMessage) -> Result<bool> {
+        if let Some(event) = Event::from_message(&message) {
+            let json = serde_json::to_string(&event).expect("Events are plain data");
+            // Without clients the event is simply missed
+            let _ = self.events.send(json);
+            self.sent += 1;
+        }
+        Ok(true)
+    }
+
+    /// Returns the number of events sent.
+    async fn finish(&mut self) -> Result<u64> {
+        Ok(self.sent)
+    }
+}
+
+async fn serve(listener: TcpListener, events: broadcast::Sender<String>) {
+    while let Ok((socket, _)) = listener.accept().await {
+        tokio::spawn(send_events(socket, events.subscribe()));
+    }
+}
+
+async fn send_events(socket: TcpStream, mut events: broadcast::Receiver<String>) {
+    let Ok(mut ws) = async_tungstenite::tokio::accept_async(socket).await else {
+        return;
+    };
+    loop {
+        let json = match events.recv().await {
+            Ok(json) => json,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if ws.send(Message::Text(json)).await.is_err() {
+            return;
+        }
+    }
+    let _ = ws.close(None).await;
+}
+
+#[test]
+fn events_as_json() {
+    let privmsg = crate::setup::make_privmsg_example();
+    let event = Event::from_message(&ServerMessage::Privmsg(privmsg)).unwrap();
+    let json: serde_json::Value = serde_json::to_value(&event).unwrap();
+    assert_eq!(json["type"], "chat");
+    assert_eq!(json["id"], "7");
+    assert_eq!(json["text"], "bread bread bread");
+    assert_eq!(json["color"], serde_json::Value::Null);
+    assert_eq!(json["badges"]["partner"], false);
+
+    let badges = Badges {
+        channel_status: Some(crate::badges::ChannelStatus::Moderator),
+        sub_badge_month: Some(crate::badges::Subscriber::Month(3)),
+        partner: true,
+    };
+    assert_eq!(
+        serde_json::to_string(&badges).unwrap(),
+        r#"{"channel_status":"moderator","sub_badge_month":{"month":3},"partner":true}"#
+    );
+    assert_eq!(
+        hex_color(RGBColor {
+            r: 255,
+            g: 0,
+            b: 16
+        }),
+        "#FF0010"
+    );
+}
+
+#[tokio::test]
+async fn clients_get_every_event() {
+    use futures_util::StreamExt;
+
+    let mut sink = OverlaySink::bind("127.0.0.1:0", 16).unwrap();
+    let url = format!("ws://{}", sink.local_addr());
+    let (mut client, _) = async_tungstenite::tokio::connect_async(url).await.unwrap();
+    // The server may not have subscribed the client yet
+    while sink.events.receiver_count() == 0 {
+        tokio::task::yield_now().await;
+    }
+
+    let privmsg = crate::setup::make_privmsg_example();
+    sink.send(ServerMessage::Privmsg(privmsg)).await.unwrap();
+    let clearchat = twitch_irc::message::IRCMessage::parse(
+        "@room-id=1;target-user-id=2;tmi-sent-ts=1 :tmi.twitch.tv CLEARCHAT #bread :crumb",
+    )
+    .unwrap();
+    let clearchat = ServerMessage::try_from(clearchat).unwrap();
+    sink.send(clearchat).await.unwrap();
+    assert_eq!(sink.finish().await.unwrap(), 2);
+
+    let Some(Ok(Message::Text(chat))) = client.next().await else {
+        panic!("No chat event")
+    };
+    assert!(chat.starts_with(r#"{"type":"chat","id":"7""#), "{chat}");
+    let Some(Ok(Message::Text(ban))) = client.next().await else {
+        panic!("No ban event")
+    };
+    assert_eq!(
+        ban,
+        r#"{"type":"clear_user","login":"crumb","timeout_secs":null}"#
+    );
+}
@@ -1,24 +1,340 @@
+use async_trait::async_trait;
 use chrono::prelude::*;
-use colored::Colorize;
-use std::io;
+use colored::{ColoredString, Colorize};
+use std::fmt;
+use std::fmt::Write as _;
 use std::io::prelude::*;
+use std::io::{self, IsTerminal};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use twitch_irc::message::NoticeMessage;
 use twitch_irc::message::PrivmsgMessage;
+use twitch_irc::message::RoomStateMessage;
 use twitch_irc::message::ServerMessage;
+use twitch_irc::message::TwitchUserBasics;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
+use crate::activity::Activity;
 use crate::badges::parse_badges;
+use crate::dedup::{Collapser, Dedup};
+use crate::error::{Error, Result};
+use crate::registry::{Name, UserRegistry};
+use crate::room_state::{ModeChange, RoomState};
+use crate::sink::Sink;
 
-pub async fn message_handler<W: Write>(
+/// Prints the chat to a terminal, or anything else [Write].
+///
+/// Stops with [Error::Notice] after a fatal NOTICE, see [is_fatal_notice].
+pub struct TerminalSink<W> {
+    out: W,
+    start_time: DateTime<Utc>,
+    room_state: RoomState,
+    room_state_tx: watch::Sender<RoomState>,
+    registry: UserRegistry,
+    dedup: Option<Collapser>,
+    /// The last line shown if it was a chat message, & how many rows it
+    /// took, for [Dedup] to add a counter to it.
+    last_chat: Option<(String, usize)>,
+    clock: StreamClock,
+    /// Shown every so often, see [TerminalSink::with_activity].
+    activity: Option<(Activity, Duration)>,
+    /// Whether chat messages are wrapped, see [TerminalSink::with_wrap].
+    wrap: bool,
+    /// Set when `out` is a terminal, see [TerminalSink::with_terminal].
+    terminal: Option<Terminal>,
+    seen: u64,
+}
+
+/// Tells a [TerminalSink] its output is a terminal, where lines can be
+/// rewritten & wrapped.
+pub struct Terminal {
+    width: Box<dyn Fn() -> Option<usize> + Send>,
+}
+
+impl Terminal {
+    /// `width()` is the number of columns of the terminal, `None` if it
+    /// can't be told.
+    pub fn new(width: impl Fn() -> Option<usize> + Send + 'static) -> Terminal {
+        Terminal {
+            width: Box::new(width),
+        }
+    }
+
+    /// The stdout of the process, if it's a terminal.
+    pub fn stdout() -> Option<Terminal> {
+        let width = || {
+            terminal_size::terminal_size_of(io::stdout())
+                .map(|(terminal_size::Width(width), _)| width.into())
+        };
+        io::stdout().is_terminal().then(|| Terminal::new(width))
+    }
+}
+
+impl<W: Write> TerminalSink<W> {
+    /// Times are shown relative to `start_time`.
+    ///
+    /// The returned receiver follows the chat modes of the channel, for use
+    /// in a status line.
+    pub fn new(out: W, start_time: DateTime<Utc>) -> (TerminalSink<W>, watch::Receiver<RoomState>) {
+        let (room_state_tx, room_state_rx) = watch::channel(RoomState::default());
+        let sink = TerminalSink {
+            out,
+            start_time,
+            room_state: RoomState::default(),
+            room_state_tx,
+            registry: UserRegistry::new(),
+            dedup: None,
+            last_chat: None,
+            clock: StreamClock::new(start_time),
+            activity: None,
+            wrap: false,
+            terminal: None,
+            seen: 0,
+        };
+        (sink, room_state_rx)
+    }
+
+    /// Starts from the names in `registry`, e.g. read from an earlier log,
+    /// so renames since then are shown too.
+    pub fn with_registry(self, registry: UserRegistry) -> TerminalSink<W> {
+        TerminalSink { registry, ..self }
+    }
+
+    /// Collapses runs of similar messages into their first, with a counter.
+    ///
+    /// The first line is rewritten in place with escape codes, so this only
+    /// takes effect with [TerminalSink::with_terminal].
+    pub fn with_dedup(self, dedup: Dedup) -> TerminalSink<W> {
+        TerminalSink {
+            dedup: Some(Collapser::new(dedup)),
+            ..self
+        }
+    }
+
+    /// The output is `terminal`, rather than a pipe or a file.
+    pub fn with_terminal(self, terminal: Terminal) -> TerminalSink<W> {
+        TerminalSink {
+            terminal: Some(terminal),
+            ..self
+        }
+    }
+
+    /// Shows a status line every `interval`, with the message rate, the
+    /// chatters in the last `window` & a sparkline of the messages each
+    /// minute.
+    pub fn with_activity(self, interval: Duration, window: Duration) -> TerminalSink<W> {
+        TerminalSink {
+            activity: Some((Activity::new(window), interval)),
+            ..self
+        }
+    }
+
+    /// Wraps chat messages at the width of the terminal, see
+    /// [TerminalSink::with_terminal] & [Formatter::wrapped].
+    ///
+    /// The width is asked for each message, so a resized terminal is
+    /// followed. Messages aren't wrapped while it can't be told.
+    pub fn with_wrap(self) -> TerminalSink<W> {
+        TerminalSink { wrap: true, ..self }
+    }
+
+    fn formatter(&self) -> Formatter {
+        let formatter = Formatter::new(self.start_time);
+        let terminal = self.terminal.as_ref().filter(|_| self.wrap);
+        match terminal.and_then(|terminal| (terminal.width)()) {
+            Some(width) => formatter.wrapped(width),
+            None => formatter,
+        }
+    }
+
+    /// Writes a line of its own, `Ok(false)` if the output was closed.
+    fn write_line(&mut self, line: impl fmt::Display) -> Result<bool> {
+        self.last_chat = None;
+        match writeln!(self.out, "{line}") {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(false),
+            Err(err) => Err(Error::Output(err)),
+        }
+    }
+
+    /// The runs of similar messages to collapse, only in a terminal.
+    fn collapser(&mut self) -> Option<&mut Collapser> {
+        self.terminal.as_ref()?;
+        self.dedup.as_mut()
+    }
+
+    /// Shows `msg`, collapsed into the run before it if it's alike, see
+    /// [TerminalSink::with_dedup].
+    fn show_chat(&mut self, msg: &PrivmsgMessage) -> Result<bool> {
+        let formatter = self.formatter();
+        let count = self.collapser().map_or(1, |collapser| collapser.check(msg));
+        match (count, self.last_chat.take()) {
+            (2.., Some((first, rows))) => {
+                let line = formatter.repeated(&first, count);
+                // Back to the start of the first line, & clear from there
+                let shown = self.write_line(format_args!("\x1b[{rows}F\x1b[J{line}"));
+                self.last_chat = Some((first, rows_taken(&line, formatter.width)));
+                shown
+            }
+            (count, _) => {
+                if let (2.., Some(collapser)) = (count, self.collapser()) {
+                    // Something else was shown since, start over
+                    collapser.reset();
+                    collapser.check(msg);
+                }
+                if self.collapser().is_none() {
+                    // Nothing to rewrite later, no need to keep the line
+                    return self.write_line(formatter.chat_line(msg));
+                }
+                let line = formatter.chat_message(msg);
+                let shown = self.write_line(&line);
+                let rows = rows_taken(&line, formatter.width);
+                self.last_chat = Some((line, rows));
+                shown
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<W: Write + Send + 'static> Sink for TerminalSink<W> {
+    async fn send(&mut self, message: ServerMessage) -> Result<bool> {
+        self.clock.observe(&message);
+        if let ServerMessage::Privmsg(msg) = &message {
+            self.seen += 1;
+            if let Some((activity, _)) = &mut self.activity {
+                activity.record(msg.server_timestamp, &msg.sender.login);
+            }
+        }
+        if let Some(previous) = self.registry.observe_message(&message) {
+            if let ServerMessage::Privmsg(msg) = &message {
+                let formatter = Formatter::new(self.start_time);
+                let line = formatter.name_change(msg.server_timestamp, &previous, &msg.sender);
+                if !self.write_line(&line)? {
+                    return Ok(false);
+                }
+            }
+        }
+        if let ServerMessage::Privmsg(msg) = &message {
+            return self.show_chat(msg);
+        }
+        if let ServerMessage::RoomState(_) | ServerMessage::Notice(_) = &message {
+            // Lines shown after the run end it
+            self.last_chat = None;
+        }
+        let fatal = match &message {
+            ServerMessage::Notice(notice) if is_fatal_notice(notice) => {
+                Some(Error::Notice(notice.message_text.clone()))
+            }
+            _ => None,
+        };
+        let room_state = &mut self.room_state;
+        let now = self.clock.now();
+        if !message_handler(message, self.start_time, now, room_state, &mut self.out)
+            .map_err(Error::Output)?
+        {
+            return Ok(false);
+        }
+        self.room_state_tx.send_if_modified(|shared| {
+            let modified = shared != room_state;
+            if modified {
+                shared.clone_from(room_state);
+            }
+            modified
+        });
+        match fatal {
+            Some(err) => Err(err),
+            None => Ok(true),
+        }
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        self.activity.as_ref().map(|(_, interval)| *interval)
+    }
+
+    /// Shows the activity status line.
+    async fn tick(&mut self) -> Result<()> {
+        let Some((activity, _)) = &mut self.activity else {
+            return Ok(());
+        };
+        // Replayed logs are shown at their own pace
+        let now = self.clock.now();
+        let mut status = activity.status(now).to_string();
+        if self.room_state != RoomState::default() {
+            status = format!("{status} | {}", self.room_state);
+        }
+        let line = Formatter::new(self.start_time).status(now, &status);
+        // A closed output stops the sink with the next message
+        self.write_line(&line).map(drop)
+    }
+
+    async fn dropped(&mut self, count: u64) -> Result<()> {
+        self.last_chat = None;
+        print_dropped(count, self.start_time, &mut self.out).map_err(Error::Output)
+    }
+
+    /// Returns the number of chat messages shown.
+    async fn finish(&mut self) -> Result<u64> {
+        Ok(self.seen)
+    }
+}
+
+/// The time in the chat, for the messages that don't carry one.
+///
+/// Follows the timestamps of the messages that do, so a replayed log keeps
+/// its own times, & goes on with the wall clock in between.
+#[derive(Clone, Copy, Debug)]
+struct StreamClock {
+    last: DateTime<Utc>,
+    seen_at: Instant,
+}
+
+impl StreamClock {
+    fn new(start_time: DateTime<Utc>) -> StreamClock {
+        StreamClock {
+            last: start_time,
+            seen_at: Instant::now(),
+        }
+    }
+
+    fn observe(&mut self, message: &ServerMessage) {
+        let time = match message {
+            ServerMessage::Privmsg(msg) => msg.server_timestamp,
+            ServerMessage::UserNotice(msg) => msg.server_timestamp,
+            ServerMessage::ClearChat(msg) => msg.server_timestamp,
+            ServerMessage::ClearMsg(msg) => msg.server_timestamp,
+            _ => return,
+        };
+        self.last = time;
+        self.seen_at = Instant::now();
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        let since = chrono::Duration::from_std(self.seen_at.elapsed()).unwrap_or_default();
+        self.last + since
+    }
+}
+
+/// Prints `message`, `Ok(false)` if the output was closed.
+///
+/// Messages without a timestamp of their own, like ROOMSTATE & NOTICE, are
+/// shown at
+/// `now`.
+pub fn message_handler<W: Write>(
     message: ServerMessage,
     start_time: DateTime<Utc>,
+    now: DateTime<Utc>,
+    room_state: &mut RoomState,
     out: &mut W,
 ) -> io::Result<bool> {
     let msg = match message {
-        ServerMessage::Privmsg(msg) => print_chat_msg(msg, start_time, out).await,
+        ServerMessage::Privmsg(msg) => print_chat_msg(msg, start_time, out),
+        ServerMessage::RoomState(msg) => print_room_state(&msg, start_time, now, room_state, out),
+        ServerMessage::Notice(msg) => print_notice(&msg, start_time, now, out),
         _ => Ok(()),
     };
     if let Err(err) = msg {
         if err.kind() != io::ErrorKind::BrokenPipe {
-            eprintln!("Write failed with {}", err);
             Err(err)
         } else {
             // Exit because pipe closed
@@ -30,31 +346,357 @@ pub async fn message_handler<W: Write>(
     }
 }
 
-async fn print_chat_msg<W: Write>(
+/// Formats the time elapsed since `start_time` as `HH:MM:SS`.
+pub(crate) fn elapsed(time: DateTime<Utc>, start_time: DateTime<Utc>) -> String {
+    Elapsed(time.signed_duration_since(start_time)).to_string()
+}
+
+/// Shows as `HH:MM:SS`, see [elapsed].
+struct Elapsed(chrono::Duration);
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}",
+            self.0.num_hours(),
+            self.0.num_minutes() % 60,
+            self.0.num_seconds() % 60,
+        )
+    }
+}
+
+/// Builds the lines the viewer prints, without printing them.
+///
+/// The strings are styled with [colored], so they follow `NO_COLOR` &
+/// `CLICOLOR_FORCE` like the viewer does, unless made
+/// [without_color](Formatter::without_color).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Formatter {
+    start_time: DateTime<Utc>,
+    color: bool,
+    /// The columns chat messages are wrapped at.
+    width: Option<usize>,
+}
+
+impl Formatter {
+    /// Times are shown relative to `start_time`.
+    pub fn new(start_time: DateTime<Utc>) -> Formatter {
+        Formatter {
+            start_time,
+            color: true,
+            width: None,
+        }
+    }
+
+    /// Plain text lines, whatever the environment says.
+    pub fn without_color(self) -> Formatter {
+        Formatter {
+            color: false,
+            ..self
+        }
+    }
+
+    /// Chat messages longer than `width` columns continue on the next lines,
+    /// indented to start under their first word.
+    pub fn wrapped(self, width: usize) -> Formatter {
+        Formatter {
+            width: Some(width),
+            ..self
+        }
+    }
+
+    fn paint(&self, text: ColoredString) -> String {
+        if self.color {
+            text.to_string()
+        } else {
+            // Deref gives the text without its style
+            (*text).to_string()
+        }
+    }
+
+    /// `HH:MM:SS badges name: text`, with the name in the user's color.
+    pub fn chat_message(&self, msg: &PrivmsgMessage) -> String {
+        self.chat_line(msg).to_string()
+    }
+
+    /// [Formatter::chat_message] without building a string, it's written
+    /// straight to wherever it's displayed.
+    pub fn chat_line<'a>(&self, msg: &'a PrivmsgMessage) -> ChatLine<'a> {
+        ChatLine {
+            formatter: *self,
+            msg,
+        }
+    }
+
+    /// `HH:MM:SS * text`, for things that happened in chat rather than
+    /// being said.
+    pub fn event(&self, time: DateTime<Utc>, text: &str) -> String {
+        let text = self.paint(format!("* {text}").italic());
+        format!("{} {}", elapsed(time, self.start_time), text)
+    }
+
+    /// A known user id chatting under a new name, see
+    /// [UserRegistry::observe].
+    pub fn name_change(
+        &self,
+        time: DateTime<Utc>,
+        previous: &Name,
+        user: &TwitchUserBasics,
+    ) -> String {
+        let text = if previous.name == user.name {
+            format!(
+                "{} changed their login from {} to {}",
+                user.name, previous.login, user.login
+            )
+        } else {
+            format!("{} is now known as {}", previous.name, user.name)
+        };
+        self.event(time, &text)
+    }
+
+    /// `HH:MM:SS ~ text`, dimmed, for a status line between the chat.
+    pub fn status(&self, time: DateTime<Utc>, text: &str) -> String {
+        let text = self.paint(format!("~ {text}").dimmed());
+        format!("{} {}", elapsed(time, self.start_time), text)
+    }
+
+    /// `line` with a counter, for the `count`th of a run of similar
+    /// messages, see [Dedup].
+    pub fn repeated(&self, line: &str, count: u64) -> String {
+        format!("{line} {}", self.paint(format!("x{count}").bold()))
+    }
+
+    /// A chat mode toggled at `time`, see [RoomState::update].
+    ///
+    /// ROOMSTATE doesn't carry a timestamp, so `time` is up to the caller.
+    pub fn mode_change(&self, time: DateTime<Utc>, change: &ModeChange) -> String {
+        self.event(time, &change.to_string())
+    }
+
+    /// A server NOTICE received at `time`, fatal ones in bold red, see
+    /// [is_fatal_notice].
+    ///
+    /// NOTICE doesn't carry a timestamp either, see [Formatter::mode_change].
+    pub fn notice(&self, time: DateTime<Utc>, msg: &NoticeMessage) -> String {
+        let text = format!("! {}", msg.message_text);
+        let text = if is_fatal_notice(msg) {
+            text.red().bold()
+        } else {
+            text.yellow()
+        };
+        format!("{} {}", elapsed(time, self.start_time), self.paint(text))
+    }
+
+    /// Tells how many messages were skipped because the output fell behind.
+    pub fn dropped(&self, count: u64) -> String {
+        let text = format!("! {count} messages dropped, the output can't keep up");
+        format!(
+            "{} {}",
+            elapsed(Utc::now(), self.start_time),
+            self.paint(text.yellow())
+        )
+    }
+}
+
+/// A chat message as it's shown, see [Formatter::chat_line].
+pub struct ChatLine<'a> {
+    formatter: Formatter,
+    msg: &'a PrivmsgMessage,
+}
+
+impl fmt::Display for ChatLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = self.msg;
+        let time = Elapsed(
+            msg.server_timestamp
+                .signed_duration_since(self.formatter.start_time),
+        );
+        let channel_badge = parse_badges(&msg.badges);
+        write!(f, "{time} {channel_badge}")?;
+        match msg.name_color {
+            // The codes `colored` writes for `truecolor`, without its
+            // allocations
+            Some(color)
+                if self.formatter.color && colored::control::SHOULD_COLORIZE.should_colorize() =>
+            {
+                write!(
+                    f,
+                    "\x1b[38;2;{};{};{}m{}\x1b[0m",
+                    color.r, color.g, color.b, msg.sender.name
+                )?;
+            }
+            _ => f.write_str(&msg.sender.name)?,
+        }
+        f.write_str(": ")?;
+        match self.formatter.width {
+            Some(width) => {
+                // Colors take no room, the plain prefix is as wide
+                let mut indent = Columns(0);
+                write!(indent, "{time} {channel_badge}{}: ", msg.sender.name)?;
+                write_wrapped(f, &msg.message_text, indent.0, width)
+            }
+            None => f.write_str(&msg.message_text),
+        }
+    }
+}
+
+/// Counts the columns of what's written to it.
+struct Columns(usize);
+
+impl fmt::Write for Columns {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.width();
+        Ok(())
+    }
+}
+
+/// Below this many columns for the text, wrapping is more in the way than
+/// not.
+const MIN_WRAP_WIDTH: usize = 20;
+
+/// Writes `text`, starting at column `indent`, in lines of at most `width`
+/// columns, the next lines indented by as many spaces.
+///
+/// Lines break between words, & inside words too long for a line.
+fn write_wrapped<W: fmt::Write>(
+    out: &mut W,
+    text: &str,
+    indent: usize,
+    width: usize,
+) -> fmt::Result {
+    let room = width.saturating_sub(indent);
+    if room < MIN_WRAP_WIDTH || text.width() <= room {
+        return out.write_str(text);
+    }
+    let mut used = 0;
+    for word in text.split(' ') {
+        let word_width = word.width();
+        if used > 0 && used + 1 + word_width > room {
+            write!(out, "\n{:indent$}", "")?;
+            used = 0;
+        }
+        if used > 0 {
+            out.write_char(' ')?;
+            used += 1;
+        }
+        if word_width <= room {
+            out.write_str(word)?;
+            used += word_width;
+            continue;
+        }
+        for c in word.chars() {
+            let char_width = c.width().unwrap_or(0);
+            if used > 0 && used + char_width > room {
+                write!(out, "\n{:indent$}", "")?;
+                used = 0;
+            }
+            out.write_char(c)?;
+            used += char_width;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn wrap(text: &str, indent: usize, width: usize) -> String {
+    let mut wrapped = String::new();
+    write_wrapped(&mut wrapped, text, indent, width).unwrap();
+    wrapped
+}
+
+/// How many rows `line` takes in a terminal `width` columns wide, counting
+/// the ones it wraps to by itself.
+fn rows_taken(line: &str, width: Option<usize>) -> usize {
+    let rows = line.lines().map(|line| match width {
+        Some(width @ 1..) => without_escapes(line).width().div_ceil(width).max(1),
+        _ => 1,
+    });
+    rows.sum::<usize>().max(1)
+}
+
+/// `line` without its color codes.
+fn without_escapes(line: &str) -> String {
+    let mut plain = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end with a letter
+            chars.by_ref().find(char::is_ascii_alphabetic);
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+fn print_chat_msg<W: Write>(
     msg: PrivmsgMessage,
     start_time: DateTime<Utc>,
     out: &mut W,
 ) -> io::Result<()> {
-    let time_since_start = msg.server_timestamp.signed_duration_since(start_time);
-    let colored_name = match msg.name_color {
-        Some(color) => msg.sender.name.truecolor(color.r, color.g, color.b),
-        None => msg.sender.name.normal(),
-    };
-    let channel_badge = parse_badges(&msg.badges).await;
-    writeln!(
-        out,
-        "{:02}:{:02}:{:02} {}{}: {}",
-        time_since_start.num_hours(),
-        time_since_start.num_minutes() % 60,
-        time_since_start.num_seconds() % 60,
-        channel_badge,
-        colored_name,
-        msg.message_text
-    )
+    writeln!(out, "{}", Formatter::new(start_time).chat_line(&msg))
 }
 
-#[tokio::test]
-async fn print_chat_msg_test() {
+/// Prints a notice line for every chat mode toggled by `msg`.
+fn print_room_state<W: Write>(
+    msg: &RoomStateMessage,
+    start_time: DateTime<Utc>,
+    now: DateTime<Utc>,
+    room_state: &mut RoomState,
+    out: &mut W,
+) -> io::Result<()> {
+    let formatter = Formatter::new(start_time);
+    for change in room_state.update(msg) {
+        writeln!(out, "{}", formatter.mode_change(now, &change))?;
+    }
+    Ok(())
+}
+
+/// See [Formatter::dropped].
+pub fn print_dropped<W: Write>(
+    count: u64,
+    start_time: DateTime<Utc>,
+    out: &mut W,
+) -> io::Result<()> {
+    writeln!(out, "{}", Formatter::new(start_time).dropped(count))
+}
+
+/// NOTICE message ids after which there is nothing more to show.
+///
+/// Most of the ids in <https://dev.twitch.tv/docs/irc/msg-id> answer a
+/// message or command we sent, like `msg_banned` or `msg_slowmode`. Those
+/// don't stop anyone from reading chat, so only the ids that mean the channel
+/// can't be read at all are listed here.
+pub const FATAL_NOTICE_IDS: &[&str] = &["msg_channel_suspended", "tos_ban"];
+
+/// Texts of the NOTICEs sent when logging in fails.
+///
+/// These come without a message id, so the exact text is matched.
+/// Anonymous logins never fail, these only apply with a token.
+pub const LOGIN_FAILURE_NOTICES: &[&str] =
+    &["Login authentication failed", "Improperly formatted auth"];
+
+/// Whether the viewer should give up after receiving this NOTICE.
+pub fn is_fatal_notice(msg: &NoticeMessage) -> bool {
+    match &msg.message_id {
+        Some(id) => FATAL_NOTICE_IDS.contains(&id.as_str()),
+        None => LOGIN_FAILURE_NOTICES.contains(&msg.message_text.as_str()),
+    }
+}
+
+/// Prints a server NOTICE in the system style.
+fn print_notice<W: Write>(
+    msg: &NoticeMessage,
+    start_time: DateTime<Utc>,
+    now: DateTime<Utc>,
+    out: &mut W,
+) -> io::Result<()> {
+    writeln!(out, "{}", Formatter::new(start_time).notice(now, msg))
+}
+
+#[test]
+fn print_chat_msg_test() {
     use chrono::Duration;
     use twitch_irc::message::TwitchUserBasics;
 
@@ -81,9 +723,7 @@ async fn print_chat_msg_test() {
 
     let mut output = vec![];
 
-    print_chat_msg(message, start_time, &mut output)
-        .await
-        .expect("Write to vec shouldn't fail");
+    print_chat_msg(message, start_time, &mut output).expect("Write to vec shouldn't fail");
     assert_eq!(
         output,
         format!("11:11:11 {sender_name}: {message_str}\n").into_bytes(),
@@ -92,8 +732,8 @@ async fn print_chat_msg_test() {
     );
 }
 
-#[tokio::test]
-async fn does_not_panic_with_broken_pipe() -> io::Result<()> {
+#[test]
+fn does_not_panic_with_broken_pipe() -> io::Result<()> {
     use std::io;
     struct PanicsBrokenPipe;
     impl Write for PanicsBrokenPipe {
@@ -109,7 +749,363 @@ async fn does_not_panic_with_broken_pipe() -> io::Result<()> {
     let message = ServerMessage::Privmsg(crate::setup::make_privmsg_example());
     let start_time = Utc::now();
     let mut output = PanicsBrokenPipe;
-    let res = message_handler(message, start_time, &mut output).await?;
+    let mut room_state = RoomState::default();
+    let res = message_handler(
+        message,
+        start_time,
+        start_time,
+        &mut room_state,
+        &mut output,
+    )?;
     assert!(!res);
     Ok(())
 }
+
+#[test]
+fn roomstate_prints_mode_changes() -> io::Result<()> {
+    use twitch_irc::message::IRCMessage;
+    let raw = "@room-id=910;slow=30;subs-only=1 :tmi.twitch.tv ROOMSTATE #bread";
+    let message = ServerMessage::try_from(IRCMessage::parse(raw).unwrap()).unwrap();
+
+    let mut room_state = RoomState::default();
+    let mut output = vec![];
+    message_handler(
+        message.clone(),
+        Utc::now(),
+        Utc::now(),
+        &mut room_state,
+        &mut output,
+    )?;
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("Slow mode enabled (30s)"), "{output}");
+    assert!(output.contains("Subscribers-only mode enabled"), "{output}");
+    assert_eq!(room_state.to_string(), "slow 30s, subs-only");
+
+    // Repeated state is not announced again
+    let mut output = vec![];
+    message_handler(
+        message,
+        Utc::now(),
+        Utc::now(),
+        &mut room_state,
+        &mut output,
+    )?;
+    assert!(output.is_empty());
+    Ok(())
+}
+
+#[test]
+fn notice_is_printed() -> io::Result<()> {
+    use twitch_irc::message::IRCMessage;
+    let raw = "@msg-id=slow_on :tmi.twitch.tv NOTICE #bread :This room is now in slow mode.";
+    let message = ServerMessage::try_from(IRCMessage::parse(raw).unwrap()).unwrap();
+
+    let mut output = vec![];
+    let mut room_state = RoomState::default();
+    message_handler(
+        message,
+        Utc::now(),
+        Utc::now(),
+        &mut room_state,
+        &mut output,
+    )?;
+    let output = String::from_utf8(output).unwrap();
+    assert!(
+        output.contains("This room is now in slow mode."),
+        "{output}"
+    );
+    Ok(())
+}
+
+#[test]
+fn fatal_notices() {
+    use twitch_irc::message::IRCMessage;
+    let parse =
+        |raw: &str| -> NoticeMessage { IRCMessage::parse(raw).unwrap().try_into().unwrap() };
+    assert!(is_fatal_notice(&parse(
+        "@msg-id=msg_channel_suspended :tmi.twitch.tv NOTICE #bread :This channel does not exist or has been suspended."
+    )));
+    assert!(is_fatal_notice(&parse(
+        "@msg-id=tos_ban :tmi.twitch.tv NOTICE #bread :The community has closed channel bread due to Terms of Service violations."
+    )));
+    assert!(is_fatal_notice(&parse(
+        ":tmi.twitch.tv NOTICE * :Login authentication failed"
+    )));
+    assert!(is_fatal_notice(&parse(
+        ":tmi.twitch.tv NOTICE * :Improperly formatted auth"
+    )));
+    assert!(!is_fatal_notice(&parse(
+        ":tmi.twitch.tv NOTICE * :Login unsuccessful, but not really"
+    )));
+    assert!(!is_fatal_notice(&parse(
+        "@msg-id=msg_banned :tmi.twitch.tv NOTICE #bread :You are permanently banned from talking in bread."
+    )));
+    assert!(!is_fatal_notice(&parse(
+        "@msg-id=emote_only_on :tmi.twitch.tv NOTICE #bread :This room is now in emote-only mode."
+    )));
+}
+
+#[test]
+fn formatter_formats_notices() {
+    use twitch_irc::message::IRCMessage;
+    let raw = "@msg-id=tos_ban :tmi.twitch.tv NOTICE #bread :The community has closed channel bread due to Terms of Service violations.";
+    let notice: NoticeMessage = IRCMessage::parse(raw).unwrap().try_into().unwrap();
+    let start = Utc::now();
+    let formatter = Formatter::new(start);
+    let line = formatter.notice(start, &notice);
+    assert!(line.starts_with("00:00:00 "), "{line}");
+    assert!(
+        line.contains("! The community has closed channel bread"),
+        "{line}"
+    );
+}
+
+#[test]
+fn formatter_without_color_is_plain() {
+    let start = Utc::now();
+    let mut msg = crate::setup::make_privmsg_example();
+    msg.name_color = Some(twitch_irc::message::RGBColor { r: 255, g: 0, b: 0 });
+    msg.server_timestamp = start + chrono::Duration::seconds(61);
+    let formatter = Formatter::new(start).without_color();
+    assert_eq!(
+        formatter.chat_message(&msg),
+        "00:01:01 7: bread bread bread"
+    );
+    assert_eq!(
+        formatter.event(start, "bread was banned"),
+        "00:00:00 * bread was banned"
+    );
+}
+
+#[tokio::test]
+async fn renames_are_announced() {
+    let start = Utc::now();
+    let mut registry = UserRegistry::new();
+    let mut msg = crate::setup::make_privmsg_example();
+    msg.server_timestamp = start;
+    registry.observe(&msg.sender, start);
+    let (sink, _) = TerminalSink::new(vec![], start);
+    let mut sink = sink.with_registry(registry);
+
+    // Same name, then a new one
+    sink.send(ServerMessage::Privmsg(msg.clone()))
+        .await
+        .unwrap();
+    msg.sender.name = "Toast".to_string();
+    msg.sender.login = "toast".to_string();
+    sink.send(ServerMessage::Privmsg(msg)).await.unwrap();
+    let output = String::from_utf8(sink.out).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 3, "{output}");
+    assert!(lines[1].ends_with(" is now known as Toast"), "{output}");
+    assert!(lines[2].contains("Toast"), "{output}");
+
+    let formatter = Formatter::new(start).without_color();
+    let previous = crate::registry::Name {
+        login: "crumb".to_string(),
+        name: "ブレッド".to_string(),
+        since: start,
+    };
+    let user = TwitchUserBasics {
+        id: "1".to_string(),
+        login: "crouton".to_string(),
+        name: "ブレッド".to_string(),
+    };
+    assert_eq!(
+        formatter.name_change(start, &previous, &user),
+        "00:00:00 * ブレッド changed their login from crumb to crouton"
+    );
+}
+
+#[tokio::test]
+async fn runs_are_collapsed_in_place() {
+    use twitch_irc::message::IRCMessage;
+
+    let start = Utc::now();
+    let (sink, _) = TerminalSink::new(vec![], start);
+    let mut sink = sink
+        .with_dedup(Dedup::default())
+        .with_terminal(Terminal::new(|| None));
+    let mut msg = crate::setup::make_privmsg_example();
+    msg.server_timestamp = start;
+    for _ in 0..3 {
+        sink.send(ServerMessage::Privmsg(msg.clone()))
+            .await
+            .unwrap();
+    }
+    let notice = "@msg-id=slow_on :tmi.twitch.tv NOTICE #bread :This room is now in slow mode.";
+    let notice = ServerMessage::try_from(IRCMessage::parse(notice).unwrap()).unwrap();
+    sink.send(notice).await.unwrap();
+    // After another line, the run starts over
+    sink.send(ServerMessage::Privmsg(msg)).await.unwrap();
+    assert_eq!(sink.finish().await.unwrap(), 4);
+
+    let output = String::from_utf8(sink.out).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 5, "{output}");
+    assert_eq!(lines[0], "00:00:00 7: bread bread bread");
+    for (line, count) in lines[1..3].iter().zip(["x2", "x3"]) {
+        // Rewrites the first line, with the counter
+        assert!(line.starts_with("\x1b[1F\x1b[J00:00:00 7: bread bread bread "));
+        assert!(line.contains(count), "{output}");
+    }
+    assert_eq!(lines[4], "00:00:00 7: bread bread bread", "{output}");
+}
+
+#[tokio::test]
+async fn runs_are_collapsed_only_in_a_terminal() {
+    /// Closed after its first line.
+    struct Closing(Vec<u8>);
+
+    impl Write for Closing {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0.contains(&b'\n') {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let msg = ServerMessage::Privmsg(crate::setup::make_privmsg_example());
+    let (sink, _) = TerminalSink::new(vec![], Utc::now());
+    let mut sink = sink.with_dedup(Dedup::default());
+    for _ in 0..2 {
+        sink.send(msg.clone()).await.unwrap();
+    }
+    let output = String::from_utf8(sink.out).unwrap();
+    assert!(!output.contains('\x1b'), "{output}");
+    assert_eq!(output.lines().count(), 2, "{output}");
+
+    // A closed output stops the sink, like for any other line
+    let (sink, _) = TerminalSink::new(Closing(vec![]), Utc::now());
+    let mut sink = sink
+        .with_dedup(Dedup::default())
+        .with_terminal(Terminal::new(|| None));
+    assert!(sink.send(msg.clone()).await.unwrap());
+    assert!(!sink.send(msg).await.unwrap());
+}
+
+#[tokio::test]
+async fn activity_is_shown_on_ticks() {
+    let start = Utc::now();
+    let (sink, _) = TerminalSink::new(vec![], start);
+    let mut sink = sink.with_activity(Duration::from_secs(30), Duration::from_secs(300));
+    assert_eq!(sink.tick_interval(), Some(Duration::from_secs(30)));
+    // Replayed from a day ago, at the times of the log
+    let mut msg = crate::setup::make_privmsg_example();
+    msg.server_timestamp = start - chrono::Duration::days(1);
+    let mut earlier = msg.clone();
+    earlier.sender.login = "crumb".to_string();
+    earlier.server_timestamp = msg.server_timestamp - chrono::Duration::minutes(10);
+    sink.send(ServerMessage::Privmsg(earlier)).await.unwrap();
+    for _ in 0..3 {
+        sink.send(ServerMessage::Privmsg(msg.clone()))
+            .await
+            .unwrap();
+    }
+    sink.tick().await.unwrap();
+
+    let output = String::from_utf8(sink.out).unwrap();
+    let status = output.lines().last().unwrap();
+    assert!(status.contains("~ "), "{output}");
+    assert!(status.contains("█ 3 msg/min, 1 chatters in 5m"), "{output}");
+    assert!(!status.contains(" | "), "{output}");
+
+    let (sink, _) = TerminalSink::new(vec![], start);
+    assert_eq!(sink.tick_interval(), None);
+}
+
+#[test]
+fn long_messages_wrap_under_their_text() {
+    let text = "the quick brown fox jumps over the lazy dog";
+    assert_eq!(wrap(text, 4, 80), text);
+    // Too narrow to bother
+    assert_eq!(wrap(text, 70, 80), text);
+    assert_eq!(
+        wrap(text, 4, 24),
+        "the quick brown fox\n    jumps over the lazy\n    dog"
+    );
+    // Wide characters take two columns, & long words are split
+    assert_eq!(
+        wrap(&format!("{} ok", "パン".repeat(6)), 0, 20),
+        format!("{}\nパン ok", "パン".repeat(5))
+    );
+
+    let mut msg = crate::setup::make_privmsg_example();
+    msg.badges = vec![twitch_irc::message::Badge {
+        name: "moderator".to_string(),
+        version: "1".to_string(),
+    }];
+    msg.server_timestamp = Utc::now();
+    msg.message_text = text.to_string();
+    let formatter = Formatter::new(msg.server_timestamp).without_color();
+    // The badge is two columns wide, like the emoji is shown
+    let indent = " ".repeat(14);
+    assert_eq!(
+        formatter.wrapped(37).chat_message(&msg),
+        format!("00:00:00 🗡️7: the quick brown fox\n{indent}jumps over the lazy dog")
+    );
+    assert_eq!(rows_taken("\x1b[1mab\x1b[0m\nabcde", Some(2)), 4);
+    assert_eq!(rows_taken("abcde", None), 1);
+}
+
+#[tokio::test]
+async fn wrapped_runs_are_collapsed_in_place() {
+    let start = Utc::now();
+    let (sink, _) = TerminalSink::new(vec![], start);
+    let mut sink = sink
+        .with_dedup(Dedup::default())
+        .with_terminal(Terminal::new(|| Some(32)))
+        .with_wrap();
+    let mut msg = crate::setup::make_privmsg_example();
+    msg.server_timestamp = start;
+    msg.message_text = "bread bread bread bread bread bread".to_string();
+    for _ in 0..2 {
+        sink.send(ServerMessage::Privmsg(msg.clone()))
+            .await
+            .unwrap();
+    }
+    let output = String::from_utf8(sink.out).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines[0], "00:00:00 7: bread bread bread", "{output}");
+    assert_eq!(lines[1], "            bread bread bread", "{output}");
+    // Both rows are rewritten
+    assert!(
+        lines[2].starts_with("\x1b[2F\x1b[J00:00:00 7: bread"),
+        "{output}"
+    );
+
+    // Pipes have no width
+    let (sink, _) = TerminalSink::new(vec![], start);
+    let mut sink = sink.with_wrap();
+    sink.send(ServerMessage::Privmsg(msg)).await.unwrap();
+    let output = String::from_utf8(sink.out).unwrap();
+    assert_eq!(output.lines().count(), 1, "{output}");
+}
+
+#[tokio::test]
+async fn untimed_lines_follow_the_chat_time() {
+    use twitch_irc::message::IRCMessage;
+
+    let start = Utc::now() - chrono::Duration::days(1);
+    let (mut sink, _) = TerminalSink::new(vec![], start);
+    let mut msg = crate::setup::make_privmsg_example();
+    // Replayed from an hour into the log
+    msg.server_timestamp = start + chrono::Duration::hours(1);
+    sink.send(ServerMessage::Privmsg(msg)).await.unwrap();
+    let raw = "@room-id=910;slow=30 :tmi.twitch.tv ROOMSTATE #bread";
+    let room_state = ServerMessage::try_from(IRCMessage::parse(raw).unwrap()).unwrap();
+    sink.send(room_state).await.unwrap();
+    let raw = "@msg-id=slow_on :tmi.twitch.tv NOTICE #bread :This room is now in slow mode.";
+    let notice = ServerMessage::try_from(IRCMessage::parse(raw).unwrap()).unwrap();
+    sink.send(notice).await.unwrap();
+
+    let output = String::from_utf8(sink.out).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert!(lines[1].starts_with("01:00:00 "), "{output}");
+    assert!(lines[2].starts_with("01:00:00 "), "{output}");
+}
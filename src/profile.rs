@@ -0,0 +1,319 @@
+//! Everything logs say about one user, for moderating them
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io::{self, prelude::*};
+use std::time::Duration;
+use twitch_irc::message::{AsRawIRC, Badge, ClearChatAction, PrivmsgMessage, ServerMessage};
+
+use crate::args::UserArgs;
+use crate::error::{Error, Result};
+use crate::registry::{Name, UserRegistry};
+use crate::room_state::short_duration;
+use crate::search::open_log;
+use crate::setup::filein_to_smsg;
+
+/// Something a user did, or that was done to them.
+#[derive(Clone, Debug)]
+pub enum Action {
+    Chat {
+        msg: Box<PrivmsgMessage>,
+        /// Deleted by a moderator.
+        deleted: bool,
+    },
+    Deleted {
+        time: DateTime<Utc>,
+        text: String,
+    },
+    TimedOut {
+        time: DateTime<Utc>,
+        length: Duration,
+    },
+    Banned {
+        time: DateTime<Utc>,
+    },
+}
+
+impl Action {
+    pub fn time(&self) -> DateTime<Utc> {
+        match self {
+            Action::Chat { msg, .. } => msg.server_timestamp,
+            Action::Deleted { time, .. }
+            | Action::TimedOut { time, .. }
+            | Action::Banned { time } => *time,
+        }
+    }
+}
+
+/// A user's messages & moderation, from any number of logs.
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    /// The login or user id asked for.
+    wanted: String,
+    /// From their latest message, logins can change.
+    pub login: Option<String>,
+    pub user_id: Option<String>,
+    /// Their names, followed by user id through renames.
+    registry: UserRegistry,
+    /// Each time their badges changed, starting with their first message.
+    pub badges: Vec<(DateTime<Utc>, Vec<Badge>)>,
+    /// In the order of the logs.
+    pub history: Vec<Action>,
+    /// What was added so far, overlapping logs repeat some of it.
+    seen: HashSet<String>,
+}
+
+impl Profile {
+    /// The profile of `who`, a login or a user id.
+    pub fn new(who: &str) -> Profile {
+        Profile {
+            wanted: who.to_string(),
+            ..Profile::default()
+        }
+    }
+
+    /// Adds `message`, if it's about them.
+    pub fn push(&mut self, message: &ServerMessage) {
+        match message {
+            ServerMessage::Privmsg(msg) => {
+                if !self.is_them(Some(&msg.sender.login), Some(&msg.sender.id))
+                    || !self.seen.insert(msg.message_id.clone())
+                {
+                    return;
+                }
+                self.login = Some(msg.sender.login.clone());
+                self.user_id = Some(msg.sender.id.clone());
+                self.registry.observe(&msg.sender, msg.server_timestamp);
+                if self.badges.last().map(|(_, badges)| badges) != Some(&msg.badges) {
+                    self.badges.push((msg.server_timestamp, msg.badges.clone()));
+                }
+                self.history.push(Action::Chat {
+                    msg: Box::new(msg.clone()),
+                    deleted: false,
+                });
+            }
+            ServerMessage::ClearMsg(msg) => {
+                if !self.is_them(Some(&msg.sender_login), None)
+                    || !self.seen.insert(format!("deleted {}", msg.message_id))
+                {
+                    return;
+                }
+                for action in &mut self.history {
+                    if let Action::Chat { msg: chat, deleted } = action {
+                        *deleted |= chat.message_id == msg.message_id;
+                    }
+                }
+                self.history.push(Action::Deleted {
+                    time: msg.server_timestamp,
+                    text: msg.message_text.clone(),
+                });
+            }
+            ServerMessage::ClearChat(msg) => {
+                let time = msg.server_timestamp;
+                let them = |login: &str, id: &str| self.is_them(Some(login), Some(id));
+                let action = match &msg.action {
+                    ClearChatAction::UserBanned {
+                        user_login,
+                        user_id,
+                    } if them(user_login, user_id) => Action::Banned { time },
+                    ClearChatAction::UserTimedOut {
+                        user_login,
+                        user_id,
+                        timeout_length,
+                    } if them(user_login, user_id) => Action::TimedOut {
+                        time,
+                        length: *timeout_length,
+                    },
+                    _ => return,
+                };
+                if self.seen.insert(msg.source.as_raw_irc()) {
+                    self.history.push(action);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn is_them(&self, login: Option<&str>, id: Option<&str>) -> bool {
+        let known_login = self.login.as_deref().unwrap_or(&self.wanted);
+        login.is_some_and(|login| login.eq_ignore_ascii_case(known_login))
+            || id.is_some_and(|id| id == self.wanted || Some(id) == self.user_id.as_deref())
+    }
+
+    /// The names they had, oldest first.
+    pub fn names(&self) -> &[Name] {
+        self.user_id
+            .as_deref()
+            .map_or(&[], |id| self.registry.names(id))
+    }
+
+    pub fn first_seen(&self) -> Option<DateTime<Utc>> {
+        self.history.iter().map(Action::time).min()
+    }
+
+    pub fn last_seen(&self) -> Option<DateTime<Utc>> {
+        self.history.iter().map(Action::time).max()
+    }
+
+    /// How many chat messages they sent, & how many of those were deleted.
+    pub fn message_count(&self) -> (usize, usize) {
+        let chats = self.history.iter().filter_map(|action| match action {
+            Action::Chat { deleted, .. } => Some(*deleted),
+            _ => None,
+        });
+        chats.fold((0, 0), |(sent, deleted), was_deleted| {
+            (sent + 1, deleted + usize::from(was_deleted))
+        })
+    }
+
+    /// The profile, then every action in the order they happened, one per
+    /// line.
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let login = self.login.as_deref().unwrap_or(&self.wanted);
+        match &self.user_id {
+            Some(id) => writeln!(out, "{login} (id {id})")?,
+            None => writeln!(out, "{login}")?,
+        }
+        if self.history.is_empty() {
+            return writeln!(out, "Not in the logs");
+        }
+        let names: Vec<_> = self.names().iter().map(|name| name.name.as_str()).collect();
+        if !names.is_empty() {
+            writeln!(out, "Names       {}", names.join(", "))?;
+        }
+        let time = |time: Option<DateTime<Utc>>| time.map(format_time).unwrap_or_default();
+        writeln!(out, "First seen  {}", time(self.first_seen()))?;
+        writeln!(out, "Last seen   {}", time(self.last_seen()))?;
+        let (sent, deleted) = self.message_count();
+        writeln!(out, "Messages    {sent}, {deleted} deleted")?;
+        let (timeouts, bans) = self
+            .history
+            .iter()
+            .fold((0, 0), |(t, b), action| match action {
+                Action::TimedOut { .. } => (t + 1, b),
+                Action::Banned { .. } => (t, b + 1),
+                _ => (t, b),
+            });
+        writeln!(out, "Timeouts    {timeouts}")?;
+        writeln!(out, "Bans        {bans}")?;
+        writeln!(out, "Badges")?;
+        for (time, badges) in &self.badges {
+            let mut names = String::new();
+            for badge in badges {
+                let comma = if names.is_empty() { "" } else { ", " };
+                write!(names, "{comma}{}/{}", badge.name, badge.version).unwrap();
+            }
+            if names.is_empty() {
+                names.push_str("none");
+            }
+            writeln!(out, "  {} {names}", format_time(*time))?;
+        }
+        writeln!(out, "History")?;
+        let mut history: Vec<_> = self.history.iter().collect();
+        history.sort_by_key(|action| action.time());
+        for action in history {
+            let time = format_time(action.time());
+            match action {
+                Action::Chat { msg, deleted } => {
+                    let marker = if *deleted { " [deleted]" } else { "" };
+                    let separator = if msg.is_action { " " } else { ": " };
+                    writeln!(
+                        out,
+                        "  {time} {}{separator}{}{marker}",
+                        msg.sender.name, msg.message_text
+                    )?;
+                }
+                Action::Deleted { text, .. } => {
+                    writeln!(out, "  {time} * A message was deleted: {text}")?
+                }
+                Action::TimedOut { length, .. } => {
+                    writeln!(out, "  {time} * Timed out for {}", short_duration(length))?
+                }
+                Action::Banned { .. } => writeln!(out, "  {time} * Banned")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Prints the profile `args` asks for.
+pub fn user(args: UserArgs) -> Result<()> {
+    let mut profile = Profile::new(&args.user);
+    for path in &args.logs {
+        for message in filein_to_smsg(open_log(path)?) {
+            profile.push(&message?);
+        }
+    }
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    profile
+        .write(&mut out)
+        .and_then(|()| out.flush())
+        .map_err(Error::Output)
+}
+
+#[cfg(test)]
+fn parse(raw: &str) -> ServerMessage {
+    let irc = twitch_irc::message::IRCMessage::parse(raw).unwrap();
+    ServerMessage::try_from(irc).unwrap()
+}
+
+#[cfg(test)]
+fn privmsg(id: u32, user_id: u32, name: &str, badges: &str, ts: u64, text: &str) -> ServerMessage {
+    let login = name.to_lowercase();
+    parse(&format!(
+        "@badge-info=;badges={badges};color=;display-name={name};emotes=;flags=;id={id};mod=0;\
+         room-id=1;subscriber=0;tmi-sent-ts={ts};turbo=0;user-id={user_id};user-type= \
+         :{login}!{login}@{login}.tmi.twitch.tv PRIVMSG #bread :{text}"
+    ))
+}
+
+#[test]
+fn profiles_follow_one_user() {
+    let messages = [
+        privmsg(1, 22, "Crumb", "", 1676000000000, "hello"),
+        privmsg(2, 33, "Toast", "", 1676000001000, "hi"),
+        privmsg(3, 22, "Crumb", "subscriber/1", 1676000002000, "spam"),
+        parse(
+            "@login=crumb;room-id=1;target-msg-id=3;tmi-sent-ts=1676000003000 \
+             :tmi.twitch.tv CLEARMSG #bread :spam",
+        ),
+        parse(
+            "@ban-duration=600;room-id=1;target-user-id=22;tmi-sent-ts=1676000004000 \
+             :tmi.twitch.tv CLEARCHAT #bread :crumb",
+        ),
+        privmsg(4, 22, "CRUMB", "subscriber/1", 1676000700000, "sorry"),
+        // Renamed, still the same id
+        privmsg(5, 22, "Crouton", "subscriber/1", 1676000800000, "new name"),
+    ];
+    // By id, then by login
+    for who in ["22", "CRUMB"] {
+        let mut profile = Profile::new(who);
+        // Overlapping logs
+        for message in messages.iter().chain(&messages[..5]) {
+            profile.push(message);
+        }
+        assert_eq!(profile.login.as_deref(), Some("crouton"));
+        let names: Vec<_> = profile.names().iter().map(|n| &n.name).collect();
+        assert_eq!(names, ["Crumb", "CRUMB", "Crouton"]);
+        assert_eq!(profile.message_count(), (4, 1));
+        assert_eq!(profile.badges.len(), 2);
+        assert_eq!(profile.history.len(), 6);
+
+        let mut out = vec![];
+        profile.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("crouton (id 22)\nNames       Crumb, CRUMB, Crouton\n"));
+        assert!(out.contains("Messages    4, 1 deleted\nTimeouts    1\nBans        0\n"));
+        assert!(out.contains("  2023-02-10 03:33:20 none\n  2023-02-10 03:33:22 subscriber/1\n"));
+        assert!(out.contains("  2023-02-10 03:33:22 Crumb: spam [deleted]\n"));
+        assert!(out.contains("  2023-02-10 03:33:24 * Timed out for 10m\n"));
+    }
+
+    let mut out = vec![];
+    Profile::new("nobody").write(&mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "nobody\nNot in the logs\n");
+}
@@ -0,0 +1,253 @@
+//! Bounded queues between the input & the outputs
+//!
+//! Unlike [tokio::sync::mpsc], a full queue can make room by dropping its
+//! oldest message, which is what the display wants during a raid.
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// What a full queue does with a new message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Wait for room, slowing down everything before it.
+    #[default]
+    Block,
+    /// Drop the oldest queued message, the sink is told how many were
+    /// dropped, see [Sink::dropped](crate::sink::Sink::dropped).
+    DropOldest,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(Policy::Block),
+            "drop-oldest" => Ok(Policy::DropOldest),
+            _ => Err(format!(
+                "unknown policy `{s}`, expected block or drop-oldest"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Policy::Block => write!(f, "block"),
+            Policy::DropOldest => write!(f, "drop-oldest"),
+        }
+    }
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    dropped: u64,
+    /// The sender is gone, or [Closer::close] was called.
+    closed: bool,
+    receiver_gone: bool,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    policy: Policy,
+    /// Wakes the receiver when something was queued or the sender left.
+    message_sent: Notify,
+    /// Wakes a blocked sender when there is room or the receiver left.
+    message_taken: Notify,
+}
+
+/// Sending half of [channel].
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Receiving half of [channel].
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Closes a [Receiver], see [Receiver::closer].
+pub struct Closer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Closer<T> {
+    /// Stops any further sends, what is already queued can still be received.
+    pub fn close(&self) {
+        close(&self.shared);
+    }
+}
+
+fn close<T>(shared: &Shared<T>) {
+    shared.state.lock().unwrap().closed = true;
+    shared.message_sent.notify_one();
+    shared.message_taken.notify_one();
+}
+
+/// The receiver is gone or closed, the message is handed back.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// Creates a queue holding at most `capacity` messages.
+///
+/// # Panics
+/// If `capacity` is 0.
+pub fn channel<T>(capacity: usize, policy: Policy) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "A queue must be able to hold a message");
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(capacity.min(1024)),
+            dropped: 0,
+            closed: false,
+            receiver_gone: false,
+        }),
+        capacity,
+        policy,
+        message_sent: Notify::new(),
+        message_taken: Notify::new(),
+    });
+    let sender = Sender {
+        shared: Arc::clone(&shared),
+    };
+    (sender, Receiver { shared })
+}
+
+impl<T> Sender<T> {
+    /// Queues `message`, waiting for room with [Policy::Block].
+    pub async fn send(&self, message: T) -> Result<(), SendError<T>> {
+        let mut message = Some(message);
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if state.closed || state.receiver_gone {
+                    return Err(SendError(message.take().unwrap()));
+                }
+                let full = state.queue.len() >= self.shared.capacity;
+                if full && self.shared.policy == Policy::DropOldest {
+                    state.queue.pop_front();
+                    state.dropped += 1;
+                }
+                if state.queue.len() < self.shared.capacity {
+                    state.queue.push_back(message.take().unwrap());
+                    drop(state);
+                    self.shared.message_sent.notify_one();
+                    return Ok(());
+                }
+            }
+            self.shared.message_taken.notified().await;
+        }
+    }
+
+    /// [Sender::send] for use outside of the runtime, e.g. in
+    /// [tokio::task::spawn_blocking].
+    pub fn blocking_send(&self, message: T) -> Result<(), SendError<T>> {
+        tokio::runtime::Handle::current().block_on(self.send(message))
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.message_sent.notify_one();
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Next message, `None` once the queue is closed & empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if let Some(message) = state.queue.pop_front() {
+                    drop(state);
+                    self.shared.message_taken.notify_one();
+                    return Some(message);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.shared.message_sent.notified().await;
+        }
+    }
+
+    /// Handle to close this queue, possibly from another task.
+    pub fn closer(&self) -> Closer<T> {
+        Closer {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// Number of messages dropped since the last call.
+    pub fn take_dropped(&mut self) -> u64 {
+        std::mem::take(&mut self.shared.state.lock().unwrap().dropped)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().receiver_gone = true;
+        self.shared.message_taken.notify_one();
+    }
+}
+
+#[tokio::test]
+async fn drop_oldest_counts_dropped() {
+    let (tx, mut rx) = channel(2, Policy::DropOldest);
+    for i in 0..5 {
+        tx.send(i).await.unwrap();
+    }
+    assert_eq!(rx.take_dropped(), 3);
+    assert_eq!(rx.take_dropped(), 0);
+    assert_eq!(rx.recv().await, Some(3));
+    assert_eq!(rx.recv().await, Some(4));
+    drop(tx);
+    assert_eq!(rx.recv().await, None);
+}
+
+#[tokio::test]
+async fn block_waits_for_room() {
+    use std::time::Duration;
+    let (tx, mut rx) = channel(1, Policy::Block);
+    tx.send(1).await.unwrap();
+
+    let blocked = tokio::time::timeout(Duration::from_millis(50), tx.send(2)).await;
+    assert!(blocked.is_err(), "Second send should wait");
+
+    let sender = tokio::spawn(async move { tx.send(3).await });
+    assert_eq!(rx.recv().await, Some(1));
+    sender.await.unwrap().unwrap();
+    assert_eq!(rx.recv().await, Some(3));
+    assert_eq!(rx.take_dropped(), 0);
+    assert_eq!(rx.recv().await, None);
+}
+
+#[tokio::test]
+async fn close_drains_queued() {
+    let (tx, mut rx) = channel(4, Policy::Block);
+    tx.send(1).await.unwrap();
+    rx.closer().close();
+    assert_eq!(tx.send(2).await, Err(SendError(2)));
+    assert_eq!(rx.recv().await, Some(1));
+    assert_eq!(rx.recv().await, None);
+}
+
+#[tokio::test]
+async fn send_fails_without_receiver() {
+    let (tx, rx) = channel(1, Policy::Block);
+    tx.send(1).await.unwrap();
+    drop(rx);
+    // Would block forever if the full queue was waited on
+    assert_eq!(tx.send(2).await, Err(SendError(2)));
+}
+
+#[test]
+fn parse_policy() {
+    assert_eq!("block".parse(), Ok(Policy::Block));
+    assert_eq!("drop-oldest".parse(), Ok(Policy::DropOldest));
+    assert!("drop-newest".parse::<Policy>().is_err());
+}
@@ -0,0 +1,110 @@
+//! Names users had, keyed by their user id
+//!
+//! Logins & display names can change, the user id of an account can't. The
+//! [UserRegistry] remembers every name seen for an id, so a renamed user can
+//! still be recognized.
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::io::prelude::*;
+use twitch_irc::message::{ServerMessage, TwitchUserBasics};
+
+use crate::error::Result;
+use crate::setup::filein_to_smsg;
+
+/// A login & display name a user had.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Name {
+    pub login: String,
+    pub name: String,
+    /// When it was first seen.
+    pub since: DateTime<Utc>,
+}
+
+/// Every name seen for each user id.
+#[derive(Clone, Debug, Default)]
+pub struct UserRegistry {
+    users: HashMap<String, Vec<Name>>,
+}
+
+impl UserRegistry {
+    pub fn new() -> UserRegistry {
+        UserRegistry::default()
+    }
+
+    /// The names of the chatters in a log written with
+    /// [log_v0](crate::logging::log_v0).
+    pub fn read<R: BufRead>(log: R) -> Result<UserRegistry> {
+        let mut registry = UserRegistry::new();
+        for message in filein_to_smsg(log) {
+            registry.observe_message(&message?);
+        }
+        Ok(registry)
+    }
+
+    /// Records the name `user` had at `time`.
+    ///
+    /// Returns their previous name if this one is new for a known id.
+    pub fn observe(&mut self, user: &TwitchUserBasics, time: DateTime<Utc>) -> Option<Name> {
+        let names = self.users.entry(user.id.clone()).or_default();
+        let previous = names.last();
+        if previous.is_some_and(|name| name.login == user.login && name.name == user.name) {
+            return None;
+        }
+        let previous = previous.cloned();
+        names.push(Name {
+            login: user.login.clone(),
+            name: user.name.clone(),
+            since: time,
+        });
+        previous
+    }
+
+    /// Records the sender of `message`, if it has one, see
+    /// [UserRegistry::observe].
+    pub fn observe_message(&mut self, message: &ServerMessage) -> Option<Name> {
+        match message {
+            ServerMessage::Privmsg(msg) => self.observe(&msg.sender, msg.server_timestamp),
+            ServerMessage::UserNotice(msg) => self.observe(&msg.sender, msg.server_timestamp),
+            _ => None,
+        }
+    }
+
+    /// The names of `user_id`, oldest first.
+    pub fn names(&self, user_id: &str) -> &[Name] {
+        self.users.get(user_id).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+fn user(id: &str, login: &str, name: &str) -> TwitchUserBasics {
+    TwitchUserBasics {
+        id: id.to_string(),
+        login: login.to_string(),
+        name: name.to_string(),
+    }
+}
+
+#[test]
+fn renames_are_recorded_by_id() {
+    let start = Utc::now();
+    let mut registry = UserRegistry::new();
+    assert_eq!(registry.observe(&user("1", "crumb", "Crumb"), start), None);
+    assert_eq!(registry.observe(&user("1", "crumb", "Crumb"), start), None);
+    assert_eq!(registry.observe(&user("2", "toast", "Toast"), start), None);
+    let later = start + chrono::Duration::days(30);
+    let previous = registry.observe(&user("1", "crouton", "Crouton"), later);
+    assert_eq!(previous.map(|name| name.name).as_deref(), Some("Crumb"));
+
+    let names: Vec<_> = registry.names("1").iter().map(|n| &n.login).collect();
+    assert_eq!(names, ["crumb", "crouton"]);
+    assert_eq!(registry.names("1")[1].since, later);
+    assert!(registry.names("3").is_empty());
+}
+
+#[test]
+fn the_sample_log_registers_its_chatters() {
+    let log = std::fs::read("tests/irc_data_no_ping").unwrap();
+    let registry = UserRegistry::read(log.as_slice()).unwrap();
+    assert!(registry.users.len() > 10);
+    assert_eq!(registry.names("135032877").len(), 1);
+}
@@ -0,0 +1,107 @@
+//! Serving a log to other chat clients, as if it were live
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::time::Duration;
+use twitch_irc::message::{AsRawIRC, IRCMessage, ServerMessage};
+
+use crate::args::ServeArgs;
+use crate::error::{Error, Result};
+use crate::logging::log_v0;
+use crate::server::{Server, Step};
+use crate::setup::shutdown_signal;
+
+/// Serves the log of `args` until Ctrl-C.
+pub async fn serve(args: ServeArgs) -> Result<()> {
+    let log = File::open(&args.log_file).map_err(|source| Error::LogOpen {
+        path: args.log_file.clone(),
+        source,
+    })?;
+    let script = replay_script(io::BufReader::new(log), args.realtime)?;
+    let messages = script.iter().filter(|s| matches!(s, Step::Send(_))).count();
+    let listen_error = |source| Error::Listen {
+        addr: args.listen.clone(),
+        source,
+    };
+    let server = Server::bind(args.listen.as_str())
+        .await
+        .map_err(listen_error)?
+        .retarget(true);
+    let addr = server.local_addr().map_err(listen_error)?;
+    let _playing = server.play(move |_| script.clone());
+    println!(
+        "Serving {messages} messages from {} on {addr}, Ctrl-C to stop",
+        args.log_file.display()
+    );
+    shutdown_signal().await;
+    Ok(())
+}
+
+/// The messages of a log, as a [Server] script.
+///
+/// Only what [log_v0] would log is replayed. With `realtime` the pauses
+/// between messages are kept, going by their `tmi-sent-ts`, messages without
+/// one follow the previous right away.
+pub fn replay_script<R: BufRead>(log: R, realtime: bool) -> Result<Vec<Step>> {
+    let mut script = vec![];
+    let mut last_sent: Option<u64> = None;
+    for line in log.lines() {
+        let line = line.map_err(Error::Input)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let irc = IRCMessage::parse(&line).map_err(|_| Error::Parse(line.clone()))?;
+        let sent = irc
+            .tags
+            .0
+            .get("tmi-sent-ts")
+            .and_then(|ts| ts.as_deref()?.parse::<u64>().ok());
+        let message = ServerMessage::try_from(irc).map_err(|_| Error::Parse(line.clone()))?;
+        let raw = message.source().as_raw_irc();
+        if !log_v0(message, &mut io::sink()).map_err(Error::Input)? {
+            continue;
+        }
+        if let (true, Some(sent)) = (realtime, sent) {
+            if let Some(pause) = last_sent.and_then(|last| sent.checked_sub(last)) {
+                if pause > 0 {
+                    script.push(Step::Sleep(Duration::from_millis(pause)));
+                }
+            }
+            last_sent = Some(sent);
+        }
+        script.push(Step::Send(raw));
+    }
+    Ok(script)
+}
+
+#[test]
+fn replays_only_logged_messages() {
+    let log = std::fs::read("tests/irc_data_no_ping").unwrap();
+    let script = replay_script(log.as_slice(), false).unwrap();
+    assert!(!script.is_empty());
+    for step in &script {
+        let Step::Send(line) = step else {
+            panic!("{step:?} without --realtime")
+        };
+        assert!(
+            !line.contains(" 001 ") && !line.contains(" JOIN "),
+            "{line}"
+        );
+    }
+}
+
+#[test]
+fn realtime_keeps_pauses() {
+    let privmsg = |ts| {
+        format!(
+            "@badge-info=;badges=;color=;display-name=crumb;emotes=;flags=;id={ts};mod=0;room-id=1;\
+             subscriber=0;tmi-sent-ts={ts};turbo=0;user-id=2;user-type= \
+             :crumb!crumb@crumb.tmi.twitch.tv PRIVMSG #bread :hi\n"
+        )
+    };
+    let log = [privmsg(1000), privmsg(3500), privmsg(3500)].concat();
+    let script = replay_script(log.as_bytes(), true).unwrap();
+    assert!(matches!(&script[0], Step::Send(_)));
+    assert_eq!(script[1], Step::Sleep(Duration::from_millis(2500)));
+    assert!(matches!(&script[2..], [Step::Send(_), Step::Send(_)]));
+    assert!(replay_script("@tags-but-no-command\n".as_bytes(), false).is_err());
+}
@@ -0,0 +1,210 @@
+//! Tracking of the chat modes of a channel, as announced by ROOMSTATE
+use std::fmt;
+use std::time::Duration;
+use twitch_irc::message::{FollowersOnlyMode, RoomStateMessage};
+
+/// The chat modes currently active in a channel.
+///
+/// Twitch sends the full state when joining a channel, after that only the
+/// settings that changed are sent.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoomState {
+    pub emote_only: bool,
+    /// `None` if followers-only mode is off, otherwise the minimum follow time.
+    pub followers_only: Option<Duration>,
+    pub r9k: bool,
+    /// `None` if slow mode is off, otherwise the delay between messages.
+    pub slow_mode: Option<Duration>,
+    pub subscribers_only: bool,
+}
+
+/// A single mode toggled by a ROOMSTATE message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModeChange {
+    EmoteOnly(bool),
+    FollowersOnly(Option<Duration>),
+    R9k(bool),
+    SlowMode(Option<Duration>),
+    SubscribersOnly(bool),
+}
+
+impl RoomState {
+    /// Apply a ROOMSTATE message, returning the modes that actually changed.
+    pub fn update(&mut self, msg: &RoomStateMessage) -> Vec<ModeChange> {
+        let mut changes = vec![];
+        if let Some(emote_only) = msg.emote_only {
+            if emote_only != self.emote_only {
+                self.emote_only = emote_only;
+                changes.push(ModeChange::EmoteOnly(emote_only));
+            }
+        }
+        if let Some(mode) = &msg.follwers_only {
+            let followers_only = match mode {
+                FollowersOnlyMode::Disabled => None,
+                FollowersOnlyMode::Enabled(duration) => Some(*duration),
+            };
+            if followers_only != self.followers_only {
+                self.followers_only = followers_only;
+                changes.push(ModeChange::FollowersOnly(followers_only));
+            }
+        }
+        if let Some(r9k) = msg.r9k {
+            if r9k != self.r9k {
+                self.r9k = r9k;
+                changes.push(ModeChange::R9k(r9k));
+            }
+        }
+        if let Some(slow) = msg.slow_mode {
+            let slow_mode = Some(slow).filter(|d| !d.is_zero());
+            if slow_mode != self.slow_mode {
+                self.slow_mode = slow_mode;
+                changes.push(ModeChange::SlowMode(slow_mode));
+            }
+        }
+        if let Some(subscribers_only) = msg.subscribers_only {
+            if subscribers_only != self.subscribers_only {
+                self.subscribers_only = subscribers_only;
+                changes.push(ModeChange::SubscribersOnly(subscribers_only));
+            }
+        }
+        changes
+    }
+}
+
+/// Formats durations the way twitch does in chat, `30s`, `10m`, `1h`.
+pub(crate) fn short_duration(duration: &Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs % 3600, secs % 60) {
+        _ if secs == 0 => format!("{secs}s"),
+        (0, _) => format!("{}h", secs / 3600),
+        (_, 0) => format!("{}m", secs / 60),
+        _ => format!("{secs}s"),
+    }
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled {
+        "enabled"
+    } else {
+        "disabled"
+    }
+}
+
+impl fmt::Display for ModeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModeChange::EmoteOnly(on) => write!(f, "Emote-only mode {}", on_off(*on)),
+            ModeChange::FollowersOnly(None) => write!(f, "Followers-only mode disabled"),
+            ModeChange::FollowersOnly(Some(d)) if d.is_zero() => {
+                write!(f, "Followers-only mode enabled")
+            }
+            ModeChange::FollowersOnly(Some(d)) => {
+                write!(f, "Followers-only mode enabled ({})", short_duration(d))
+            }
+            ModeChange::R9k(on) => write!(f, "Unique chat (r9k) mode {}", on_off(*on)),
+            ModeChange::SlowMode(None) => write!(f, "Slow mode disabled"),
+            ModeChange::SlowMode(Some(d)) => write!(f, "Slow mode enabled ({})", short_duration(d)),
+            ModeChange::SubscribersOnly(on) => write!(f, "Subscribers-only mode {}", on_off(*on)),
+        }
+    }
+}
+
+/// Compact summary suitable for a status line, e.g. `slow 30s, emote-only`.
+impl fmt::Display for RoomState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut modes = vec![];
+        if let Some(d) = &self.slow_mode {
+            modes.push(format!("slow {}", short_duration(d)));
+        }
+        if self.emote_only {
+            modes.push("emote-only".to_string());
+        }
+        match &self.followers_only {
+            Some(d) if d.is_zero() => modes.push("followers-only".to_string()),
+            Some(d) => modes.push(format!("followers-only {}", short_duration(d))),
+            None => (),
+        }
+        if self.subscribers_only {
+            modes.push("subs-only".to_string());
+        }
+        if self.r9k {
+            modes.push("r9k".to_string());
+        }
+        if modes.is_empty() {
+            write!(f, "no chat modes")
+        } else {
+            write!(f, "{}", modes.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+fn parse_roomstate(raw: &str) -> RoomStateMessage {
+    use twitch_irc::message::IRCMessage;
+    IRCMessage::parse(raw)
+        .expect("Preset irc message")
+        .try_into()
+        .expect("Valid ROOMSTATE")
+}
+
+#[test]
+fn join_roomstate_with_no_modes_changes_nothing() {
+    let msg = parse_roomstate("@r9k=0;slow=0;emote-only=0;subs-only=0;followers-only=-1;room-id=483194031 :tmi.twitch.tv ROOMSTATE #harukakaribu");
+    let mut state = RoomState::default();
+    assert!(state.update(&msg).is_empty());
+    assert_eq!(state, RoomState::default());
+}
+
+#[test]
+fn roomstate_reports_only_changes() {
+    let mut state = RoomState::default();
+    let msg = parse_roomstate("@room-id=483194031;slow=30 :tmi.twitch.tv ROOMSTATE #harukakaribu");
+    assert_eq!(
+        state.update(&msg),
+        vec![ModeChange::SlowMode(Some(Duration::from_secs(30)))]
+    );
+    assert!(state.update(&msg).is_empty());
+
+    let msg = parse_roomstate(
+        "@room-id=483194031;emote-only=1;followers-only=10 :tmi.twitch.tv ROOMSTATE #harukakaribu",
+    );
+    assert_eq!(
+        state.update(&msg),
+        vec![
+            ModeChange::EmoteOnly(true),
+            ModeChange::FollowersOnly(Some(Duration::from_secs(600)))
+        ]
+    );
+    assert_eq!(
+        state.to_string(),
+        "slow 30s, emote-only, followers-only 10m"
+    );
+
+    let msg = parse_roomstate("@room-id=483194031;slow=0 :tmi.twitch.tv ROOMSTATE #harukakaribu");
+    assert_eq!(state.update(&msg), vec![ModeChange::SlowMode(None)]);
+    assert_eq!(state.slow_mode, None);
+}
+
+#[test]
+fn display_mode_change() {
+    assert_eq!(
+        ModeChange::SlowMode(Some(Duration::from_secs(120))).to_string(),
+        "Slow mode enabled (2m)"
+    );
+    assert_eq!(
+        ModeChange::SlowMode(Some(Duration::from_secs(3600))).to_string(),
+        "Slow mode enabled (1h)"
+    );
+    assert_eq!(
+        ModeChange::SlowMode(Some(Duration::from_secs(90))).to_string(),
+        "Slow mode enabled (90s)"
+    );
+    assert_eq!(
+        ModeChange::SubscribersOnly(false).to_string(),
+        "Subscribers-only mode disabled"
+    );
+    assert_eq!(
+        ModeChange::FollowersOnly(Some(Duration::ZERO)).to_string(),
+        "Followers-only mode enabled"
+    );
+}
@@ -0,0 +1,393 @@
+//! Finding messages & events across logs
+//!
+//! Each log is streamed twice: first for when it starts & what moderators
+//! removed, then for its entries, so hits are shown like the viewer shows
+//! them without holding the whole log. Only the hits & their context are
+//! formatted. Lines that aren't irc are skipped & counted on stderr.
+use chrono::{DateTime, Utc};
+use flate2::bufread::MultiGzDecoder;
+use regex::Regex;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::Path;
+use twitch_irc::message::{PrivmsgMessage, ServerMessage};
+
+use crate::args::SearchArgs;
+use crate::error::{Error, Result};
+use crate::export::{Entry, EntryKind, EntryReader, Filter, Removal};
+use crate::pretty_print::Formatter;
+use crate::setup::filein_to_smsg;
+
+/// What [Search::matches] looks for, every entry matches by default.
+#[derive(Clone, Debug, Default)]
+pub struct Search {
+    /// Matched against the text of messages & events.
+    pub pattern: Option<Regex>,
+    pub filter: Filter,
+    /// Any of these kinds, every kind if empty.
+    pub kinds: Vec<EntryKind>,
+}
+
+impl Search {
+    /// Whether `entry`, which happened at `time` in a log starting at
+    /// `start`, matches.
+    pub fn matches(&self, entry: &Entry, time: DateTime<Utc>, start: DateTime<Utc>) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&entry.kind()))
+            && self.filter.keeps(entry, time, start)
+            && self
+                .pattern
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(entry.text()))
+    }
+}
+
+/// Runs the search of `args` over its logs.
+pub fn search(args: SearchArgs) -> Result<()> {
+    let search = Search {
+        pattern: args.pattern,
+        filter: Filter {
+            from: args.from,
+            to: args.to,
+            users: args.user,
+        },
+        kinds: args.kinds,
+    };
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    for path in &args.logs {
+        let skipped = search_log(path, &search, args.context, &mut out)?;
+        if skipped > 0 {
+            eprintln!(
+                "{}: skipped {skipped} lines that aren't irc",
+                path.display()
+            );
+        }
+    }
+    out.flush().map_err(Error::Output)
+}
+
+/// Prints the hits of `search` in the log at `path`, with `context` entries
+/// before & after each. Returns how many lines weren't irc.
+fn search_log<W: Write>(path: &Path, search: &Search, context: usize, out: &mut W) -> Result<u64> {
+    let mut skipped = 0;
+    let (removed, start) = read_ahead(messages(open_log(path)?, &mut skipped))?;
+    let start = start.unwrap_or_default();
+    let mut printer = Printer::new(path, Formatter::new(start), context, out);
+    let mut reader = EntryReader::default();
+    let mut index = 0;
+    let mut last_time = start;
+    for message in messages(open_log(path)?, &mut 0) {
+        let (_, entries) = reader.read(message?);
+        for mut entry in entries {
+            // Modes happen at the entry before them, like Transcript::times
+            let time = entry.time().unwrap_or(last_time);
+            last_time = time;
+            if let Entry::Chat { msg, removed: hit } = &mut entry {
+                *hit = removed.hits(index, msg);
+            }
+            let matches = search.matches(&entry, time, start);
+            printer
+                .push(index, entry, time, matches)
+                .map_err(Error::Output)?;
+            index += 1;
+        }
+    }
+    printer.finish().map_err(Error::Output)?;
+    Ok(skipped)
+}
+
+/// The messages of `log`, lines that aren't irc are counted in `skipped`.
+fn messages(
+    log: Box<dyn BufRead>,
+    skipped: &mut u64,
+) -> impl Iterator<Item = Result<ServerMessage>> + '_ {
+    filein_to_smsg(log).filter(|message| match message {
+        Err(Error::Parse(_)) => {
+            *skipped += 1;
+            false
+        }
+        _ => true,
+    })
+}
+
+/// What moderators removed from a log, & when it starts.
+fn read_ahead(
+    messages: impl Iterator<Item = Result<ServerMessage>>,
+) -> Result<(Removed, Option<DateTime<Utc>>)> {
+    let mut reader = EntryReader::default();
+    let mut removed = Removed::default();
+    let mut start = None;
+    let mut count = 0;
+    for message in messages {
+        let (removal, entries) = reader.read(message?);
+        if let Some(removal) = removal {
+            removed.add(removal, count);
+        }
+        start = start.or_else(|| entries.iter().find_map(Entry::time));
+        count += entries.len();
+    }
+    Ok((removed, start))
+}
+
+/// The chat messages of a log that moderators removed.
+#[derive(Debug, Default)]
+struct Removed {
+    messages: HashSet<String>,
+    /// How many entries came before each user's last timeout or ban.
+    users: HashMap<String, usize>,
+    /// How many entries came before chat was last cleared.
+    all: usize,
+}
+
+impl Removed {
+    fn add(&mut self, removal: Removal, before: usize) {
+        match removal {
+            Removal::Message(id) => {
+                self.messages.insert(id);
+            }
+            Removal::User(login) => {
+                self.users.insert(login, before);
+            }
+            Removal::All => self.all = before,
+        }
+    }
+
+    /// Whether `msg`, the `index`th entry, was removed later on.
+    fn hits(&self, index: usize, msg: &PrivmsgMessage) -> bool {
+        index < self.all
+            || self.messages.contains(&msg.message_id)
+            || self
+                .users
+                .get(&msg.sender.login)
+                .is_some_and(|&before| index < before)
+    }
+}
+
+/// Prints the hits of a log as its entries go by: `path`, then each hit
+/// with `context` entries before & after, groups separated by `--` like
+/// grep. Overlapping or touching groups are merged.
+struct Printer<'a, W> {
+    path: &'a Path,
+    formatter: Formatter,
+    context: usize,
+    out: &'a mut W,
+    /// The last `context` entries, in case the next one is a hit.
+    before: VecDeque<(usize, Entry, DateTime<Utc>)>,
+    /// How many entries are still printed after the last hit.
+    after: usize,
+    /// The index of the last entry printed.
+    printed: Option<usize>,
+}
+
+impl<'a, W: Write> Printer<'a, W> {
+    fn new(path: &'a Path, formatter: Formatter, context: usize, out: &'a mut W) -> Self {
+        Printer {
+            path,
+            formatter,
+            context,
+            out,
+            before: VecDeque::with_capacity(context + 1),
+            after: 0,
+            printed: None,
+        }
+    }
+
+    fn push(
+        &mut self,
+        index: usize,
+        entry: Entry,
+        time: DateTime<Utc>,
+        hit: bool,
+    ) -> io::Result<()> {
+        if hit {
+            let first = self.before.front().map_or(index, |(index, ..)| *index);
+            match self.printed {
+                None => writeln!(self.out, "{}", self.path.display())?,
+                Some(printed) if first > printed + 1 => writeln!(self.out, "--")?,
+                Some(_) => {}
+            }
+            while let Some((index, entry, time)) = self.before.pop_front() {
+                self.print(index, &entry, time)?;
+            }
+            self.after = self.context;
+            self.print(index, &entry, time)
+        } else if self.after > 0 {
+            self.after -= 1;
+            self.print(index, &entry, time)
+        } else {
+            self.before.push_back((index, entry, time));
+            if self.before.len() > self.context {
+                self.before.pop_front();
+            }
+            Ok(())
+        }
+    }
+
+    fn print(&mut self, index: usize, entry: &Entry, time: DateTime<Utc>) -> io::Result<()> {
+        self.printed = Some(index);
+        writeln!(self.out, "{}", entry.line(time, &self.formatter))
+    }
+
+    /// Ends the hits with a blank line, if there were any.
+    fn finish(self) -> io::Result<()> {
+        match self.printed {
+            Some(_) => writeln!(self.out),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Opens a log, gzipped or not.
+pub(crate) fn open_log(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path).map_err(|source| Error::LogOpen {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let mut log = io::BufReader::new(file);
+    // Going by the content, rotated logs aren't always named .gz
+    let gzipped = log
+        .fill_buf()
+        .map_err(Error::Input)?
+        .starts_with(&[0x1f, 0x8b]);
+    if gzipped {
+        Ok(Box::new(io::BufReader::new(MultiGzDecoder::new(log))))
+    } else {
+        Ok(Box::new(log))
+    }
+}
+
+#[cfg(test)]
+fn sample_log() -> crate::export::Transcript {
+    let log = std::fs::read("tests/irc_data_no_ping").unwrap();
+    crate::export::Transcript::read(log.as_slice()).unwrap()
+}
+
+/// Indexes of the entries of `transcript` that `search` matches.
+#[cfg(test)]
+fn hits_in(search: &Search, transcript: &crate::export::Transcript) -> Vec<usize> {
+    let start = transcript.start.unwrap_or_default();
+    let entries = transcript.entries.iter().zip(transcript.times());
+    let hits = entries
+        .enumerate()
+        .filter(|(_, (entry, time))| search.matches(entry, *time, start));
+    hits.map(|(index, _)| index).collect()
+}
+
+#[test]
+fn hits_match_every_filter() {
+    let transcript = sample_log();
+    let everything = hits_in(&Search::default(), &transcript);
+    assert_eq!(everything.len(), transcript.entries.len());
+
+    let pattern = Search {
+        pattern: Some(Regex::new("(?i)wanna se+$").unwrap()),
+        ..Search::default()
+    };
+    let hits = hits_in(&pattern, &transcript);
+    assert_eq!(hits.len(), 2);
+    assert_eq!(transcript.entries[hits[0]].user(), Some("inkspitter"));
+
+    let user = Search {
+        filter: Filter {
+            users: vec!["InkSpitter".to_string()],
+            ..Filter::default()
+        },
+        kinds: vec![EntryKind::Chat],
+        ..Search::default()
+    };
+    assert_eq!(hits_in(&user, &transcript), hits);
+    let not_chat = Search {
+        kinds: vec![EntryKind::Sub],
+        ..pattern
+    };
+    assert!(hits_in(&not_chat, &transcript).is_empty());
+    let modes = Search {
+        kinds: vec![EntryKind::Mode, EntryKind::Ban],
+        ..Search::default()
+    };
+    let modes = hits_in(&modes, &transcript);
+    assert!(!modes.is_empty());
+    assert!(modes
+        .iter()
+        .all(|&i| transcript.entries[i].kind() == EntryKind::Mode));
+}
+
+#[test]
+fn context_groups_merge() {
+    let time = Utc::now();
+    let formatter = Formatter::new(time);
+    let print = |hits: &[usize], context| {
+        let mut out = vec![];
+        let mut printer = Printer::new(Path::new("chat.log"), formatter, context, &mut out);
+        for (index, text) in "abcdefghij".chars().enumerate() {
+            let entry = Entry::Event {
+                time: Some(time),
+                user: None,
+                kind: EntryKind::Clear,
+                text: text.to_string(),
+            };
+            printer
+                .push(index, entry, time, hits.contains(&index))
+                .unwrap();
+        }
+        printer.finish().unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let line = |text: &str| match text {
+            "--" | "" | "chat.log" => text.to_string(),
+            _ => formatter.event(time, text),
+        };
+        (out, line)
+    };
+    let expect = |hits: &[usize], context, lines: &str| {
+        let (out, line) = print(hits, context);
+        let expected: Vec<String> = lines.split(' ').map(line).collect();
+        assert_eq!(out, expected.join("\n") + "\n", "{hits:?} {context}");
+    };
+    expect(&[0, 5], 0, "chat.log a -- f ");
+    expect(&[0, 6], 2, "chat.log a b c -- e f g h i ");
+    expect(&[0, 4, 9], 1, "chat.log a b -- d e f -- i j ");
+    expect(&[0, 5], 2, "chat.log a b c d e f g h ");
+    expect(&[2, 3], 1, "chat.log b c d e ");
+    assert_eq!(print(&[], 1).0, "");
+}
+
+#[test]
+fn removed_and_bad_lines() {
+    let mut log = std::fs::read_to_string("tests/irc_data_no_ping").unwrap();
+    log.push_str("@not-irc\n");
+    log.push_str(
+        "@login=inkspitter;target-msg-id=277c68fd-3e90-49e3-98d3-7575d0cfa99b;\
+         tmi-sent-ts=1713727115000 :tmi.twitch.tv CLEARMSG #harukakaribu :wanna seeeeee\n",
+    );
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(log.as_bytes()).unwrap();
+    let search = Search {
+        pattern: Some(Regex::new("(?i)wanna se+$").unwrap()),
+        ..Search::default()
+    };
+    let mut out = vec![];
+    let skipped = search_log(file.path(), &search, 0, &mut out).unwrap();
+    assert_eq!(skipped, 1);
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 5, "{out}");
+    assert!(lines[1].ends_with("wanna seeeeee [removed]"), "{out}");
+    assert_eq!(lines[2], "--");
+    assert!(lines[3].ends_with("i wanna seeee"), "{out}");
+}
+
+#[test]
+fn gzipped_logs_are_read() {
+    use flate2::write::GzEncoder;
+
+    let log = std::fs::read("tests/irc_data_no_ping").unwrap();
+    let mut gz = GzEncoder::new(tempfile::NamedTempFile::new().unwrap(), Default::default());
+    gz.write_all(&log).unwrap();
+    let file = gz.finish().unwrap();
+    let transcript = crate::export::Transcript::read(open_log(file.path()).unwrap()).unwrap();
+    assert_eq!(transcript.entries.len(), sample_log().entries.len());
+    assert!(matches!(
+        open_log(Path::new("tests/nope.log.gz")),
+        Err(Error::LogOpen { .. })
+    ));
+}
@@ -0,0 +1,592 @@
+//! A minimal twitch compatible irc server
+//!
+//! Speaks just enough of twitch's irc for a client to log in, join & chat:
+//! CAP, NICK, JOIN, PART & PING, over plain tcp or WebSocket. What it sends
+//! once a client joins is scripted, which makes it a stand-in for twitch in
+//! tests.
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+use twitch_irc::message::{AsRawIRC, IRCMessage};
+
+use crate::endpoint::{Endpoint, Transport};
+
+/// One thing a server does after a client joined.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Sends a raw irc line.
+    Send(String),
+    Sleep(Duration),
+    /// Drops the connection, like a network error.
+    Disconnect,
+}
+
+/// Accepts clients, see [Server::play].
+pub struct Server {
+    listener: TcpListener,
+    confirm_joins: bool,
+    retarget: bool,
+}
+
+impl Server {
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(addr).await?,
+            confirm_joins: true,
+            retarget: false,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// The plain tcp [Endpoint] of this server.
+    pub fn endpoint(&self) -> io::Result<Endpoint> {
+        let addr = self.local_addr()?;
+        Ok(Endpoint {
+            host: addr.ip().to_string(),
+            port: addr.port(),
+            transport: Transport::Tcp,
+        })
+    }
+
+    /// Whether JOINs are answered, twitch doesn't for channels that don't
+    /// exist. On by default.
+    pub fn confirm_joins(mut self, confirm: bool) -> Server {
+        self.confirm_joins = confirm;
+        self
+    }
+
+    /// Whether scripted messages go to the channel the client joined,
+    /// whichever channel they were sent in originally. Off by default.
+    pub fn retarget(mut self, retarget: bool) -> Server {
+        self.retarget = retarget;
+        self
+    }
+
+    /// Serves clients until dropped.
+    ///
+    /// `script` is called with the number of each connection, starting at 0,
+    /// & its steps are played once that connection joins a channel.
+    pub fn play<F>(self, mut script: F) -> Playing
+    where
+        F: FnMut(usize) -> Vec<Step> + Send + 'static,
+    {
+        let received = Arc::new(Mutex::new(vec![]));
+        let (played_tx, played) = watch::channel(0);
+        let played_tx = Arc::new(played_tx);
+        let task = {
+            let received = Arc::clone(&received);
+            tokio::spawn(async move {
+                let mut connections = 0;
+                while let Ok((socket, _)) = self.listener.accept().await {
+                    let steps = script(connections);
+                    connections += 1;
+                    let (received, played_tx) = (Arc::clone(&received), Arc::clone(&played_tx));
+                    let (confirm_joins, retarget) = (self.confirm_joins, self.retarget);
+                    tokio::spawn(async move {
+                        let connection = Connection {
+                            received,
+                            confirm_joins,
+                            retarget,
+                            nick: "justinfan12345".to_string(),
+                            joined: None,
+                        };
+                        // A client going away is no error of the server
+                        let _ = connection.accept(socket, steps, &played_tx).await;
+                    });
+                }
+            })
+        };
+        Playing {
+            received,
+            played,
+            task,
+        }
+    }
+}
+
+/// A running [Server].
+pub struct Playing {
+    received: Arc<Mutex<Vec<String>>>,
+    played: watch::Receiver<usize>,
+    task: JoinHandle<()>,
+}
+
+impl Playing {
+    /// Every line clients sent so far, in order.
+    pub fn received(&self) -> Vec<String> {
+        self.received.lock().unwrap().clone()
+    }
+
+    /// Waits until `count` scripts were played to the end.
+    pub async fn played(&self, count: usize) {
+        let mut played = self.played.clone();
+        // The sender lives as long as the server
+        let _ = played.wait_for(|played| *played >= count).await;
+    }
+}
+
+impl Drop for Playing {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+struct Connection {
+    received: Arc<Mutex<Vec<String>>>,
+    confirm_joins: bool,
+    retarget: bool,
+    nick: String,
+    /// The first channel joined, for [Server::retarget].
+    joined: Option<String>,
+}
+
+/// What the script of a connection asks of it.
+enum Out {
+    Line(String),
+    Close,
+}
+
+impl Connection {
+    /// Serves plain irc, or irc over WebSocket to clients that start with
+    /// an HTTP request.
+    async fn accept(
+        self,
+        socket: TcpStream,
+        steps: Vec<Step>,
+        played: &Arc<watch::Sender<usize>>,
+    ) -> io::Result<()> {
+        let mut start = [0; 4];
+        let peeked = socket.peek(&mut start).await?;
+        if start[..peeked] != *b"GET " {
+            return self.run(socket, steps, played).await;
+        }
+        let ws = async_tungstenite::tokio::accept_async(socket)
+            .await
+            .map_err(io::Error::other)?;
+        self.run(crate::websocket::into_stream(ws), steps, played)
+            .await
+    }
+
+    async fn run<S: AsyncRead + AsyncWrite>(
+        mut self,
+        socket: S,
+        steps: Vec<Step>,
+        played: &Arc<watch::Sender<usize>>,
+    ) -> io::Result<()> {
+        let (read, mut write) = tokio::io::split(socket);
+        let mut lines = BufReader::new(read).lines();
+        let (out_tx, mut out) = mpsc::unbounded_channel();
+        let mut steps = Some(steps);
+        loop {
+            let reply = tokio::select! {
+                line = lines.next_line() => {
+                    let Some(line) = line? else { return Ok(()) };
+                    self.received.lock().unwrap().push(line.clone());
+                    if line.starts_with("JOIN ") {
+                        if let Some(steps) = steps.take() {
+                            play(steps, out_tx.clone(), played);
+                        }
+                    }
+                    self.reply(&line)
+                }
+                out = out.recv() => match out {
+                    Some(Out::Line(line)) => vec![self.retargeted(line)],
+                    Some(Out::Close) | None => return Ok(()),
+                },
+            };
+            for line in reply {
+                write.write_all(format!("{line}\r\n").as_bytes()).await?;
+            }
+        }
+    }
+
+    /// `line` sent to the joined channel, if retargeting.
+    fn retargeted(&self, line: String) -> String {
+        let (true, Some(joined)) = (self.retarget, &self.joined) else {
+            return line;
+        };
+        match IRCMessage::parse(&line) {
+            Ok(mut message) if message.params.first().is_some_and(|p| p.starts_with('#')) => {
+                message.params[0] = joined.clone();
+                message.as_raw_irc()
+            }
+            _ => line,
+        }
+    }
+
+    /// The lines twitch would answer `line` with.
+    fn reply(&mut self, line: &str) -> Vec<String> {
+        let (command, params) = line.split_once(' ').unwrap_or((line, ""));
+        let nick = &self.nick;
+        match command {
+            "CAP" => {
+                let caps = params.split_once(':').map_or("", |(_, caps)| caps);
+                vec![format!(":tmi.twitch.tv CAP * ACK :{caps}")]
+            }
+            "NICK" => {
+                self.nick = params.to_string();
+                let nick = &self.nick;
+                vec![
+                    format!(":tmi.twitch.tv 001 {nick} :Welcome, GLHF!"),
+                    format!(":tmi.twitch.tv 002 {nick} :Your host is tmi.twitch.tv"),
+                    format!(":tmi.twitch.tv 003 {nick} :This server is rather new"),
+                    format!(":tmi.twitch.tv 004 {nick} :-"),
+                    format!(":tmi.twitch.tv 375 {nick} :-"),
+                    format!(":tmi.twitch.tv 372 {nick} :You are in a maze of twisty passages."),
+                    format!(":tmi.twitch.tv 376 {nick} :>"),
+                ]
+            }
+            "JOIN" if self.joined.is_none() => {
+                let channel = params.split(',').next().unwrap_or(params);
+                self.joined = Some(channel.to_string());
+                self.reply(line)
+            }
+            "JOIN" if self.confirm_joins => params
+                .split(',')
+                .map(|channel| format!(":{nick}!{nick}@{nick}.tmi.twitch.tv JOIN {channel}"))
+                .collect(),
+            "PART" => params
+                .split(',')
+                .map(|channel| format!(":{nick}!{nick}@{nick}.tmi.twitch.tv PART {channel}"))
+                .collect(),
+            "PING" => {
+                let argument = params.trim_start_matches(':');
+                vec![format!(":tmi.twitch.tv PONG tmi.twitch.tv :{argument}")]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// Plays `steps` into `out`, counting it in `played` once done.
+fn play(steps: Vec<Step>, out: mpsc::UnboundedSender<Out>, played: &Arc<watch::Sender<usize>>) {
+    let played = Arc::clone(played);
+    tokio::spawn(async move {
+        for step in steps {
+            let sent = match step {
+                Step::Send(line) => out.send(Out::Line(line)),
+                Step::Sleep(duration) => {
+                    tokio::time::sleep(duration).await;
+                    Ok(())
+                }
+                Step::Disconnect => out.send(Out::Close),
+            };
+            if sent.is_err() {
+                // The client left early
+                break;
+            }
+        }
+        played.send_modify(|played| *played += 1);
+    });
+}
+
+#[tokio::test]
+async fn answers_the_handshake() -> io::Result<()> {
+    use tokio::io::AsyncReadExt;
+    let server = Server::bind("127.0.0.1:0").await?;
+    let addr = server.local_addr()?;
+    let playing = server.play(|_| vec![Step::Send("bread".to_string()), Step::Disconnect]);
+
+    let mut client = TcpStream::connect(addr).await?;
+    client
+        .write_all(b"CAP REQ :twitch.tv/tags\r\nNICK bread\r\nJOIN #bread\r\nPING hi\r\n")
+        .await?;
+    let mut answer = String::new();
+    client.read_to_string(&mut answer).await?;
+    let answer: Vec<&str> = answer.lines().collect();
+    assert_eq!(answer[0], ":tmi.twitch.tv CAP * ACK :twitch.tv/tags");
+    assert_eq!(answer[1], ":tmi.twitch.tv 001 bread :Welcome, GLHF!");
+    assert!(answer.contains(&":bread!bread@bread.tmi.twitch.tv JOIN #bread"));
+    assert!(answer.contains(&":tmi.twitch.tv PONG tmi.twitch.tv :hi"));
+    assert!(answer.contains(&"bread"));
+
+    playing.played(1).await;
+    assert_eq!(playing.received()[1], "NICK bread");
+    Ok(())
+}
+
+#[tokio::test]
+async fn joins_can_go_unanswered() -> io::Result<()> {
+    use tokio::io::AsyncReadExt;
+    let server = Server::bind("127.0.0.1:0").await?.confirm_joins(false);
+    let addr = server.local_addr()?;
+    let pause = Step::Sleep(Duration::from_millis(10));
+    let _playing = server.play(move |_| vec![pause.clone(), Step::Disconnect]);
+
+    let mut client = TcpStream::connect(addr).await?;
+    client.write_all(b"NICK bread\r\nJOIN #bread\r\n").await?;
+    let mut answer = String::new();
+    client.read_to_string(&mut answer).await?;
+    assert!(!answer.contains("JOIN"), "{answer}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn retargets_to_the_joined_channel() -> io::Result<()> {
+    use tokio::io::AsyncReadExt;
+    let server = Server::bind("127.0.0.1:0").await?.retarget(true);
+    let addr = server.local_addr()?;
+    let privmsg = ":crumb!crumb@crumb.tmi.twitch.tv PRIVMSG #harukakaribu :hi".to_string();
+    let _playing = server.play(move |_| vec![Step::Send(privmsg.clone()), Step::Disconnect]);
+
+    let mut client = TcpStream::connect(addr).await?;
+    client.write_all(b"NICK bread\r\nJOIN #bread\r\n").await?;
+    let mut answer = String::new();
+    client.read_to_string(&mut answer).await?;
+    assert!(
+        answer.contains(":crumb!crumb@crumb.tmi.twitch.tv PRIVMSG #bread hi"),
+        "{answer}"
+    );
+    Ok(())
+}
+
+// The viewer against this server, end to end
+
+#[cfg(test)]
+type Shutdown = std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>;
+
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Output {
+    fn lines(&self) -> Vec<String> {
+        let output = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+        output.lines().map(str::to_string).collect()
+    }
+
+    /// The index of the first line containing `text`.
+    fn position(&self, text: &str) -> Option<usize> {
+        self.lines().iter().position(|line| line.contains(text))
+    }
+}
+
+#[cfg(test)]
+impl std::io::Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        std::io::Write::write(&mut *self.0.lock().unwrap(), buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn privmsg(id: u32, text: &str) -> Step {
+    Step::Send(format!(
+        "@badge-info=;badges=;color=#FF0000;display-name=crumb;emotes=;first-msg=0;flags=;id={id};mod=0;\
+         room-id=1;subscriber=0;tmi-sent-ts=1676000000000;turbo=0;user-id=2;user-type= \
+         :crumb!crumb@crumb.tmi.twitch.tv PRIVMSG #bread :{text}"
+    ))
+}
+
+#[cfg(test)]
+fn args() -> crate::args::Args {
+    crate::args::Args {
+        channel_name: "bread".to_string(),
+        transport: Transport::Tcp,
+        ..crate::args::Args::default()
+    }
+}
+
+/// Runs the viewer against `server` until `shutdown`, `args` pick the
+/// transport.
+#[cfg(test)]
+async fn view<S>(
+    server: Server,
+    script: Vec<Vec<Step>>,
+    args: crate::args::Args,
+    shutdown: S,
+) -> Session
+where
+    S: FnOnce(Arc<Playing>) -> Shutdown,
+{
+    // The session sets the endpoint from `args` itself
+    let lock = crate::endpoint::ENDPOINT_LOCK.lock().await;
+    let endpoint = server.endpoint().unwrap();
+    let args = crate::args::Args {
+        host: Some(endpoint.host),
+        port: Some(endpoint.port),
+        ..args
+    };
+    let playing = Arc::new(server.play(move |i| script.get(i).cloned().unwrap_or_default()));
+    let output = Output::default();
+    let res = crate::setup::init_until(
+        args,
+        io::empty(),
+        output.clone(),
+        None,
+        shutdown(Arc::clone(&playing)),
+    )
+    .await;
+    drop(lock);
+    Session {
+        res,
+        output,
+        received: playing.received(),
+    }
+}
+
+#[cfg(test)]
+struct Session {
+    res: crate::error::Result<()>,
+    output: Output,
+    /// What the viewer sent to the server.
+    received: Vec<String>,
+}
+
+/// Stops once `scripts` were played, leaving time for the last messages.
+#[cfg(test)]
+fn after_played(scripts: usize) -> impl FnOnce(Arc<Playing>) -> Shutdown {
+    move |playing| {
+        Box::pin(async move {
+            playing.played(scripts).await;
+            tokio::time::sleep(Duration::from_millis(300)).await;
+        })
+    }
+}
+
+/// For sessions that end on their own.
+#[cfg(test)]
+fn never(_: Arc<Playing>) -> Shutdown {
+    Box::pin(std::future::pending())
+}
+
+#[cfg(test)]
+async fn bind() -> Server {
+    Server::bind("127.0.0.1:0").await.unwrap()
+}
+
+#[tokio::test]
+async fn joins_and_shows_chat_in_order() {
+    let script = vec![vec![
+        privmsg(1, "first"),
+        privmsg(2, "second"),
+        Step::Sleep(Duration::from_millis(50)),
+        privmsg(3, "third"),
+    ]];
+    let session = view(bind().await, script, args(), after_played(1)).await;
+    session.res.unwrap();
+    assert!(session.received.contains(&"JOIN #bread".to_string()));
+    assert!(session.received.contains(&"PART #bread".to_string()));
+    let output = &session.output;
+    let positions: Vec<_> = ["first", "second", "third"]
+        .iter()
+        .map(|text| output.position(text))
+        .collect();
+    assert!(
+        positions.iter().all(Option::is_some) && positions.windows(2).all(|w| w[0] < w[1]),
+        "{:?}",
+        output.lines()
+    );
+}
+
+#[tokio::test]
+async fn rejoins_after_a_reconnect() {
+    let script = vec![
+        vec![privmsg(1, "before"), Step::Disconnect],
+        vec![privmsg(2, "after")],
+    ];
+    let session = view(bind().await, script, args(), after_played(2)).await;
+    session.res.unwrap();
+    let joins = session.received.iter().filter(|l| *l == "JOIN #bread");
+    assert_eq!(joins.count(), 2, "{:?}", session.received);
+    let (before, after) = (
+        session.output.position("before"),
+        session.output.position("after"),
+    );
+    assert!(
+        before.is_some() && before < after,
+        "{:?}",
+        session.output.lines()
+    );
+}
+
+#[tokio::test]
+async fn twitch_reconnect_is_followed() {
+    let script = vec![
+        vec![Step::Send(":tmi.twitch.tv RECONNECT".to_string())],
+        vec![privmsg(1, "reconnected")],
+    ];
+    let session = view(bind().await, script, args(), after_played(2)).await;
+    session.res.unwrap();
+    assert!(session.output.position("reconnected").is_some());
+}
+
+#[tokio::test]
+async fn connects_over_websocket() {
+    let script = vec![vec![privmsg(1, "over websocket")]];
+    let args = crate::args::Args {
+        transport: Transport::Ws,
+        ..args()
+    };
+    let session = view(bind().await, script, args, after_played(1)).await;
+    session.res.unwrap();
+    assert!(session.received.contains(&"JOIN #bread".to_string()));
+    assert!(session.output.position("over websocket").is_some());
+}
+
+#[tokio::test]
+async fn fatal_notice_is_an_error() {
+    let script = vec![vec![
+        privmsg(1, "hello"),
+        Step::Send(
+            "@msg-id=msg_channel_suspended :tmi.twitch.tv NOTICE #bread \
+             :This channel does not exist or has been suspended."
+                .to_string(),
+        ),
+    ]];
+    let session = view(bind().await, script, args(), never).await;
+    let err = session.res.unwrap_err();
+    assert!(matches!(err, crate::error::Error::Notice(_)), "{err}");
+    assert_eq!(err.exit_code(), 2);
+    assert!(session.output.position("hello").is_some());
+}
+
+#[tokio::test]
+async fn unconfirmed_join_times_out() {
+    let server = bind().await.confirm_joins(false);
+    let args = crate::args::Args {
+        join_timeout: 1,
+        ..args()
+    };
+    let session = view(server, vec![], args, never).await;
+    let err = session.res.unwrap_err();
+    assert!(matches!(err, crate::error::Error::JoinTimeout(_)), "{err}");
+    assert_eq!(err.exit_code(), 3);
+}
+
+#[tokio::test]
+async fn zero_join_timeout_waits() {
+    let server = bind().await.confirm_joins(false);
+    let args = crate::args::Args {
+        join_timeout: 0,
+        ..args()
+    };
+    let after_timeout =
+        |_| -> Shutdown { Box::pin(tokio::time::sleep(Duration::from_millis(1500))) };
+    let session = view(server, vec![], args, after_timeout).await;
+    session.res.unwrap();
+}
+
+#[tokio::test]
+async fn replayed_log_is_shown_in_the_joined_channel() {
+    let log = std::fs::read("tests/irc_data_no_ping").unwrap();
+    let script = crate::replay::replay_script(log.as_slice(), false).unwrap();
+    let server = bind().await.retarget(true);
+    let session = view(server, vec![script], args(), after_played(1)).await;
+    session.res.unwrap();
+    // The log is of #harukakaribu, the viewer joined #bread
+    let chat = session.output.lines();
+    assert!(chat.len() > 10, "{chat:?}");
+}
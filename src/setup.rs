@@ -1,60 +1,413 @@
+use std::fmt;
 use std::fs::{File, OpenOptions};
+use std::future::Future;
 use std::io::{self, prelude::*};
-use tokio::sync::mpsc::{self, UnboundedReceiver};
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::task::JoinHandle;
 use twitch_irc::login::StaticLoginCredentials;
 use twitch_irc::message::ServerMessage;
+use twitch_irc::transport::tcp::TCPTransport;
+use twitch_irc::ClientConfig;
 use twitch_irc::TwitchIRCClient;
-use twitch_irc::{ClientConfig, SecureTCPTransport};
 
 use crate::args::Args;
-use crate::logging::log_v0;
-use crate::pretty_print::message_handler;
+use crate::auth::Credentials;
+use crate::endpoint::{set_endpoint, Configured, Pool};
+use crate::error::{Error, Result};
+use crate::input::{run_input, Chat, RecentMessages};
+use crate::logging::{FlushPolicy, LogSink};
+use crate::moderation::{default_audit_log, Api, AuditLog, Helix};
+use crate::overlay::OverlaySink;
+use crate::pretty_print::{Terminal, TerminalSink};
+use crate::queue::{self, Policy, Receiver};
+use crate::registry::UserRegistry;
+use crate::sink::Broadcaster;
+
+pub type TwitchClient = TwitchIRCClient<TCPTransport<Configured>, StaticLoginCredentials>;
+
+/// Runs the viewer, writing the chat to `stdout`.
+///
+/// `terminal` is set when `stdout` is a terminal, see [Terminal::stdout].
+pub async fn init<W, R>(args: Args, stdin: R, stdout: W, terminal: Option<Terminal>) -> Result<()>
+where
+    W: Write + Send + 'static,
+    R: Read + Send + 'static,
+{
+    init_until(args, stdin, stdout, terminal, shutdown_signal()).await
+}
+
+/// What happened during a session, printed when it ends.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionSummary {
+    pub duration: chrono::Duration,
+    /// Chat messages shown.
+    pub messages_seen: u64,
+    /// `None` without a log file.
+    pub messages_logged: Option<u64>,
+}
 
-pub type TwitchClient = TwitchIRCClient<SecureTCPTransport, StaticLoginCredentials>;
+impl fmt::Display for SessionSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Session lasted {:02}:{:02}:{:02}, {} messages seen",
+            self.duration.num_hours(),
+            self.duration.num_minutes() % 60,
+            self.duration.num_seconds() % 60,
+            self.messages_seen
+        )?;
+        if let Some(logged) = self.messages_logged {
+            write!(f, ", {logged} logged")?;
+        }
+        Ok(())
+    }
+}
+
+/// [init], but stops reading new messages once `shutdown` completes.
+///
+/// Messages already received are still written before returning.
+pub async fn init_until<W, R, S>(
+    args: Args,
+    stdin: R,
+    stdout: W,
+    terminal: Option<Terminal>,
+    shutdown: S,
+) -> Result<()>
+where
+    W: Write + Send + 'static,
+    R: Read + Send + 'static,
+    S: Future<Output = ()> + Send + 'static,
+{
+    let start_time = chrono::Utc::now();
+    let (messages_seen, messages_logged) =
+        run_session(args, stdin, stdout, terminal, shutdown).await?;
+    let end_time = chrono::Utc::now();
+    let summary = SessionSummary {
+        duration: end_time.signed_duration_since(start_time),
+        messages_seen,
+        messages_logged,
+    };
+    println!("Logging ended at {}", end_time);
+    println!("{summary}");
+    Ok(())
+}
 
-pub async fn init<W, R>(args: Args, stdin: R, stdout: W)
+/// Runs the viewer, returning the number of messages seen & logged.
+async fn run_session<W, R, S>(
+    args: Args,
+    stdin: R,
+    stdout: W,
+    terminal: Option<Terminal>,
+    shutdown: S,
+) -> Result<(u64, Option<u64>)>
 where
     W: Write + Send + 'static,
     R: Read + Send + 'static,
+    S: Future<Output = ()> + Send + 'static,
 {
     if args.from_stdin {
-        let (handle, recv) = filein_channel_task_create(stdin);
-        let (handle_res, _) = tokio::join!(handle, init_with_input(args, recv, stdout));
-        handle_res.unwrap();
+        if args.login {
+            return Err(Error::Credentials(
+                "stdin can't be both the chat & the input line, drop --from-stdin".to_string(),
+            ));
+        }
+        let (handle, recv) = filein_channel_task_create(stdin, args.buffer_size);
+        let stop_handle = stop_on_shutdown(recv.closer(), shutdown);
+        let res = init_with_input(args, recv, stdout, terminal, None).await;
+        let interrupted = stop_handle.is_finished();
+        stop_handle.abort();
+        if interrupted {
+            // The reader may be stuck waiting on stdin, don't wait for it
+            return res;
+        }
+        // Output errors come first, a closed output also stops the input
+        let counts = res?;
+        handle.await.unwrap().map(|()| counts)
     } else {
-        let (incoming_messages, client) = build_irc_client();
-        client.join(args.channel_name.clone()).unwrap();
-        init_with_input(args, incoming_messages, stdout).await;
+        let credentials = match args.login {
+            true => Some(Credentials::load(args.credentials.as_deref())?),
+            false => None,
+        };
+        let login = credentials
+            .as_ref()
+            .map(|credentials| credentials.login.clone());
+        let audit_log = match login {
+            Some(_) => Some(open_audit_log(&args)?),
+            None => None,
+        };
+        let recent = login.as_ref().map(|_| RecentMessages::default());
+        let channel = args.channel_name.clone();
+        let helix = credentials
+            .as_ref()
+            .map(|credentials| Helix::new(Api::default(), credentials, &channel));
+        // Reconnects during the session go there too
+        let _endpoint = set_endpoint(args.endpoint());
+        let (incoming_messages, client) = build_irc_client(credentials, &args.pool());
+        // Leave the channel, so twitch stops sending & the queue can drain
+        let part = {
+            let (client, channel) = (client.clone(), channel.clone());
+            async move {
+                shutdown.await;
+                client.part(channel);
+            }
+        };
+        let incoming_messages = bounded_input(incoming_messages, args.buffer_size);
+        stop_on_shutdown(incoming_messages.closer(), part);
+        let join_timeout = args.join_timeout;
+        client
+            .join(channel.clone())
+            .map_err(|err| Error::InvalidChannel(err.to_string()))?;
+        if let (Some(login), Some(recent), Some(helix), Some(audit_log)) =
+            (login, &recent, helix, audit_log)
+        {
+            let chat = Chat {
+                client: client.clone(),
+                channel: channel.clone(),
+                login,
+                recent: recent.clone(),
+                helix,
+                audit_log,
+            };
+            let input = run_input(chat, stdin);
+            tokio::spawn(async move {
+                if let Err(err) = input.await {
+                    eprintln!("error: {err}");
+                }
+            });
+        }
+
+        let viewer = init_with_input(args, incoming_messages, stdout, terminal, recent);
+        if join_timeout == 0 {
+            // Wait as long as it takes
+            return viewer.await;
+        }
+        tokio::pin!(viewer);
+        let timeout = Duration::from_secs(join_timeout);
+        let joined = wait_for_join(&channel, timeout, || {
+            let (client, channel) = (&client, channel.clone());
+            async move { client.get_channel_status(channel).await.1 }
+        });
+        tokio::select! {
+            res = &mut viewer => return res,
+            res = joined => res?,
+        }
+        viewer.await
+    }
+}
+
+/// Completes on Ctrl-C, or SIGTERM on unix.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            // Without a handler the default of exiting immediately still applies
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => tokio::select! {
+                _ = ctrl_c => (),
+                _ = term.recv() => (),
+            },
+            Err(_) => ctrl_c.await,
+        }
     }
+    #[cfg(not(unix))]
+    ctrl_c.await;
+}
+
+/// Closes the input once `shutdown` completes.
+///
+/// What was already received is still shown & logged.
+fn stop_on_shutdown<T, S>(input: queue::Closer<T>, shutdown: S) -> JoinHandle<()>
+where
+    T: Send + 'static,
+    S: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        shutdown.await;
+        input.close();
+    })
+}
+
+/// Moves messages from the client's unbounded channel into a bounded queue.
+///
+/// The client has no way to slow down twitch, but this way a slow output
+/// at least can't make every queue after it grow.
+pub(crate) fn bounded_input<T: Send + 'static>(
+    mut incoming: UnboundedReceiver<T>,
+    capacity: usize,
+) -> Receiver<T> {
+    let (tx, rx) = queue::channel(capacity, Policy::Block);
+    tokio::spawn(async move {
+        while let Some(message) = incoming.recv().await {
+            if tx.send(message).await.is_err() {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+/// How often the join status is checked in [wait_for_join].
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Waits until `is_joined` reports the channel as joined.
+///
+/// Twitch gives no error for channels that don't exist, the join is simply
+/// never confirmed. So after `timeout` we give up with
+/// [Error::JoinTimeout].
+pub async fn wait_for_join<F, Fut>(channel: &str, timeout: Duration, mut is_joined: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let poll = async {
+        let mut interval = tokio::time::interval(JOIN_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            if is_joined().await {
+                return;
+            }
+        }
+    };
+    tokio::time::timeout(timeout, poll)
+        .await
+        .map_err(|_| Error::JoinTimeout(channel.to_string()))
 }
 
+/// Returns the number of messages seen & logged, see [SessionSummary].
 async fn init_with_input<W>(
     args: Args,
-    incoming_messages: UnboundedReceiver<ServerMessage>,
+    incoming_messages: Receiver<ServerMessage>,
     stdout: W,
-) where
+    terminal: Option<Terminal>,
+    recent: Option<RecentMessages>,
+) -> Result<(u64, Option<u64>)>
+where
     W: Write + Send + 'static,
 {
-    if args.log_file.is_some() {
-        let file = open_log_file(&args).unwrap();
-        let mut file = io::BufWriter::new(file);
-
-        let (handle, rx1, mut rx2) = receiver_splitter(incoming_messages);
-        let fancy_task = setup_fancy_output(rx1, stdout);
-        let log_task = tokio::spawn(async move {
-            while let Some(message) = rx2.recv().await {
-                log_v0(message, &mut file).await;
-            }
-        });
-        let (task1, task2, task3) = tokio::join!(handle, fancy_task, log_task);
-        task1.unwrap();
-        task2.unwrap();
-        task3.unwrap();
-    } else {
-        let join_handle = setup_fancy_output(incoming_messages, stdout);
-        join_handle.await.unwrap();
+    let outputs = build_sinks(&args, stdout, terminal, recent)?;
+    let results = outputs.broadcaster.run(incoming_messages).await;
+    // Display errors come first, they are the reason the log stopped early
+    let counts = results.into_iter().collect::<Result<Vec<u64>>>()?;
+    let logged = outputs.log.map(|log| counts[log]);
+    Ok((counts[outputs.terminal], logged))
+}
+
+/// The outputs of a session, & which of their counts go in the
+/// [SessionSummary].
+struct Outputs {
+    broadcaster: Broadcaster,
+    /// Indexes in the results of [Broadcaster::run].
+    terminal: usize,
+    log: Option<usize>,
+}
+
+/// Every output `args` asks for, the terminal first & then the log file.
+/// Outputs only needed by other features, like the overlay, come last.
+///
+/// New outputs are added here.
+fn build_sinks<W: Write + Send + 'static>(
+    args: &Args,
+    mut stdout: W,
+    tty: Option<Terminal>,
+    recent: Option<RecentMessages>,
+) -> Result<Outputs> {
+    // Renames since the log was started are shown too
+    let registry = match &args.log_file {
+        Some(path) if args.append && path.exists() => {
+            let log = File::open(path).map_err(|source| Error::LogOpen {
+                path: path.clone(),
+                source,
+            })?;
+            UserRegistry::read(io::BufReader::new(log))?
+        }
+        _ => UserRegistry::new(),
+    };
+    let log_file = match &args.log_file {
+        Some(path) => Some(open_log_file(args).map_err(|source| Error::LogOpen {
+            path: path.clone(),
+            source,
+        })?),
+        None => None,
+    };
+    let overlay = match &args.overlay {
+        Some(addr) => Some(OverlaySink::bind(addr.as_str(), args.buffer_size).map_err(
+            |source| Error::Listen {
+                addr: addr.clone(),
+                source,
+            },
+        )?),
+        None => None,
+    };
+
+    let startup_time = chrono::Utc::now();
+    writeln!(stdout, "Logging started at {}", startup_time).map_err(Error::Output)?;
+    if let Some(overlay) = &overlay {
+        writeln!(stdout, "Overlay events at ws://{}", overlay.local_addr())
+            .map_err(Error::Output)?;
+    }
+    let mut broadcaster = Broadcaster::new(args.buffer_size);
+    let (terminal, _) = TerminalSink::new(stdout, startup_time);
+    let mut terminal = terminal.with_registry(registry);
+    // Collapsing & wrapping only happen in a terminal, pipes have no width
+    // & lines are easier to process whole
+    if let Some(tty) = tty {
+        terminal = terminal.with_terminal(tty);
+    }
+    if let Some(dedup) = args.dedup() {
+        terminal = terminal.with_dedup(dedup);
     }
+    if !args.no_wrap {
+        terminal = terminal.with_wrap();
+    }
+    if let Some(interval) = args.activity {
+        let interval = Duration::from_secs(interval.get());
+        let window = Duration::from_secs(args.activity_window * 60);
+        terminal = terminal.with_activity(interval, window);
+    }
+    let terminal_index = broadcaster.sink_count();
+    broadcaster.add_sink(terminal, args.display_policy);
+    let mut log = None;
+    if let Some(file) = log_file {
+        let flush = FlushPolicy::from_secs(args.flush_interval);
+        log = Some(broadcaster.sink_count());
+        // The log never drops messages
+        broadcaster.add_sink(LogSink::new(io::BufWriter::new(file), flush), Policy::Block);
+    }
+    if let Some(recent) = recent {
+        // Only the latest message of a chatter matters
+        broadcaster.add_sink(recent, Policy::DropOldest);
+    }
+    if let Some(overlay) = overlay {
+        // Overlays show live chat, stale messages are no use
+        broadcaster.add_sink(overlay, Policy::DropOldest);
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &args.archive {
+        let archive = crate::archive::Archive::open(path)?;
+        // Like the log, the archive never drops messages
+        broadcaster.add_sink(crate::archive::ArchiveSink::new(archive), Policy::Block);
+    }
+    Ok(Outputs {
+        broadcaster,
+        terminal: terminal_index,
+        log,
+    })
+}
+
+/// Opens `--audit-log`, or the default audit log.
+fn open_audit_log(args: &Args) -> Result<AuditLog> {
+    let path = args
+        .audit_log
+        .clone()
+        .or_else(default_audit_log)
+        .ok_or_else(|| Error::Credentials("no home directory, pass --audit-log".to_string()))?;
+    AuditLog::open(&path).map_err(|source| Error::LogOpen { path, source })
 }
 
 fn open_log_file(args: &Args) -> io::Result<File> {
@@ -66,73 +419,52 @@ fn open_log_file(args: &Args) -> io::Result<File> {
         .open(log_file)
 }
 
-fn filein_to_smsg<R: BufRead>(input: R) -> impl Iterator<Item = io::Result<ServerMessage>> {
+pub(crate) fn filein_to_smsg<R: BufRead>(input: R) -> impl Iterator<Item = Result<ServerMessage>> {
     use twitch_irc::message::IRCMessage;
     input.lines().map(|l| {
-        l.map(|raw| {
-            let msg = IRCMessage::parse(raw.as_ref()).unwrap();
-            ServerMessage::try_from(msg).unwrap()
-        })
+        let raw = l.map_err(Error::Input)?;
+        IRCMessage::parse(raw.as_ref())
+            .ok()
+            .and_then(|msg| ServerMessage::try_from(msg).ok())
+            .ok_or(Error::Parse(raw))
     })
 }
 
-fn filein_channel_task_create<R: Read + Send + 'static>(
+pub(crate) fn filein_channel_task_create<R: Read + Send + 'static>(
     input: R,
-) -> (JoinHandle<()>, UnboundedReceiver<ServerMessage>) {
-    let (tx, rx) = mpsc::unbounded_channel();
-    let stdin_read_task = tokio::spawn(async move {
+    capacity: usize,
+) -> (JoinHandle<Result<()>>, Receiver<ServerMessage>) {
+    // Reading a file is always faster than showing it, wait instead of dropping
+    let (tx, rx) = queue::channel(capacity, Policy::Block);
+    // Reads block, keep them off the runtime's worker threads
+    let stdin_read_task = tokio::task::spawn_blocking(move || {
         let input = io::BufReader::new(input);
         for msg in filein_to_smsg(input) {
-            tx.send(msg.unwrap()).unwrap();
-        }
-    });
-    (stdin_read_task, rx)
-}
-
-fn receiver_splitter<T>(
-    mut incoming: UnboundedReceiver<T>,
-) -> (JoinHandle<()>, UnboundedReceiver<T>, UnboundedReceiver<T>)
-where
-    T: Clone + std::marker::Send + 'static,
-{
-    let (tx1, rx1) = mpsc::unbounded_channel();
-    let (tx2, rx2) = mpsc::unbounded_channel();
-    let handle = tokio::spawn(async move {
-        while let Some(message) = incoming.recv().await {
-            if tx1.send(message.clone()).is_err() || tx2.send(message).is_err() {
-                return;
+            if tx.blocking_send(msg?).is_err() {
+                // Output stopped, nothing left to read for
+                break;
             }
         }
+        Ok(())
     });
-    (handle, rx1, rx2)
+    (stdin_read_task, rx)
 }
 
 /// Simplified version of TwitchIRCClient::new with default config
-pub fn build_irc_client() -> (UnboundedReceiver<ServerMessage>, TwitchClient) {
-    let config = ClientConfig::default();
+///
+/// Anonymous without `credentials`, connects to [endpoint()](crate::endpoint::endpoint).
+pub fn build_irc_client(
+    credentials: Option<Credentials>,
+    pool: &Pool,
+) -> (UnboundedReceiver<ServerMessage>, TwitchClient) {
+    let mut config = match credentials {
+        Some(credentials) => ClientConfig::new_simple(credentials.into_login()),
+        None => ClientConfig::default(),
+    };
+    pool.apply(&mut config);
     TwitchClient::new(config)
 }
 
-pub fn setup_fancy_output<W: Write + Send + 'static>(
-    mut incoming: UnboundedReceiver<ServerMessage>,
-    stdout: W,
-) -> JoinHandle<()> {
-    let startup_time = chrono::Utc::now();
-    println!("Logging started at {}", startup_time);
-
-    tokio::spawn(async move {
-        let mut stdout = stdout;
-        while let Some(message) = incoming.recv().await {
-            if !message_handler(message, startup_time, &mut stdout)
-                .await
-                .expect("Failed to write message")
-            {
-                break;
-            }
-        }
-    })
-}
-
 /// This was created with a lot of trial & error, mainly the tags
 #[allow(dead_code)]
 pub const PRIVMSG_EXAMPLE: &str = "@room-id=910;user-id=8;display-name=7;badge-info=;badges=;color=;emotes=;tmi-sent-ts=666;id=7 :bread!bread!bread@bread.tmi.twitch.tv PRIVMSG #bread :bread bread bread";
@@ -241,7 +573,9 @@ async fn read_from_stdin() {
 
     let send_output = WriteLockBuf(Arc::clone(&output));
     let test_input = io::Cursor::new(test_input);
-    init(test_args, test_input, send_output).await;
+    init(test_args, test_input, send_output, None)
+        .await
+        .unwrap();
 
     let output = { String::from(std::str::from_utf8(&output.lock().unwrap()).unwrap()) };
 
@@ -253,6 +587,73 @@ async fn read_from_stdin() {
     );
 }
 
+#[tokio::test]
+async fn fatal_notice_stops_output() {
+    let test_args = Args {
+        channel_name: String::from("&"),
+        from_stdin: true,
+        ..Default::default()
+    };
+
+    let mut test_input = vec![];
+    writeln!(test_input, "@msg-id=msg_channel_suspended :tmi.twitch.tv NOTICE #bread :This channel does not exist or has been suspended.").unwrap();
+    writeln!(test_input, "{}", PRIVMSG_EXAMPLE).unwrap();
+    let test_input = io::Cursor::new(test_input);
+
+    let res = init(test_args, test_input, io::sink(), None).await;
+    assert!(matches!(res, Err(Error::Notice(_))), "{res:?}");
+}
+
+#[tokio::test]
+async fn fatal_notice_stops_logging_with_open_input() {
+    use tempfile::NamedTempFile;
+    use twitch_irc::message::IRCMessage;
+    let path = NamedTempFile::new().expect("Could not get temp path");
+    let test_args = Args {
+        channel_name: String::from("&"),
+        log_file: Some(path.as_ref().to_path_buf()),
+        ..Default::default()
+    };
+    let raw = "@msg-id=msg_channel_suspended :tmi.twitch.tv NOTICE #bread :This channel does not exist or has been suspended.";
+    let notice = ServerMessage::try_from(IRCMessage::parse(raw).unwrap()).unwrap();
+
+    // Keep the input open, like a live connection that has gone quiet
+    let (tx, rx) = queue::channel(16, Policy::Block);
+    tx.send(notice).await.unwrap();
+    let viewer = init_with_input(test_args, rx, io::sink(), None, None);
+    let res = tokio::time::timeout(Duration::from_secs(5), viewer)
+        .await
+        .expect("Viewer should stop without further input");
+    assert!(matches!(res, Err(Error::Notice(_))), "{res:?}");
+    drop(tx);
+}
+
+#[tokio::test]
+async fn wait_for_join_confirms() {
+    let mut polls = 0;
+    let res = wait_for_join("bread", Duration::from_secs(5), || {
+        polls += 1;
+        let joined = polls >= 3;
+        async move { joined }
+    })
+    .await;
+    assert!(res.is_ok());
+    assert_eq!(polls, 3);
+}
+
+#[tokio::test]
+async fn wait_for_join_times_out() {
+    let timeout = Duration::from_millis(200);
+    let res = wait_for_join("bread", timeout, || async { false }).await;
+    match res {
+        Err(err @ Error::JoinTimeout(_)) => {
+            assert_eq!(err.exit_code(), 3);
+            assert!(err.to_string().contains("#bread"));
+        }
+        res => panic!("Expected a join timeout, got {res:?}"),
+    }
+}
+
 #[test]
 fn test_text_to_server_message() {
     use twitch_irc::message::IRCMessage;
@@ -288,7 +689,7 @@ async fn create_stdin_task() {
     writeln!(input, "{}", PRIVMSG_EXAMPLE).unwrap();
     let input = io::Cursor::new(input);
 
-    let (handle, mut incoming) = filein_channel_task_create(input);
+    let (handle, mut incoming) = filein_channel_task_create(input, 16);
     let first = incoming.recv().await.unwrap();
     assert_eq!(first.source(), &irc_msg);
 
@@ -296,22 +697,221 @@ async fn create_stdin_task() {
     assert_eq!(second.source(), &irc_msg);
     assert!(incoming.recv().await.is_none());
 
-    handle.await.unwrap();
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn invalid_input_is_an_error() {
+    let input = io::Cursor::new("@tags-but-no-command\n");
+    let (handle, mut incoming) = filein_channel_task_create(input, 16);
+    assert!(incoming.recv().await.is_none());
+    let res = handle.await.unwrap();
+    assert!(matches!(res, Err(Error::Parse(_))), "{res:?}");
+}
+
+#[tokio::test]
+async fn unwritable_log_file_is_an_error() {
+    let dir = tempfile::tempdir().expect("Could not get temp dir");
+    let test_args = Args {
+        channel_name: String::from("&"),
+        from_stdin: true,
+        // A directory can't be opened as a file
+        log_file: Some(dir.path().to_path_buf()),
+        ..Default::default()
+    };
+    let test_input = io::Cursor::new(format!("{PRIVMSG_EXAMPLE}\n"));
+    let res = init(test_args, test_input, io::sink(), None).await;
+    match res {
+        Err(err @ Error::LogOpen { .. }) => assert_eq!(err.exit_code(), 5),
+        res => panic!("Expected the log file to fail, got {res:?}"),
+    }
+}
+
+#[tokio::test]
+async fn shutdown_drains_queued_messages() {
+    let (tx, mut rx) = queue::channel(16, Policy::Block);
+    tx.send(1).await.unwrap();
+    tx.send(2).await.unwrap();
+
+    stop_on_shutdown(rx.closer(), async {}).await.unwrap();
+    assert!(
+        tx.send(3).await.is_err(),
+        "Input should be closed after shutdown"
+    );
+    assert_eq!(rx.recv().await, Some(1));
+    assert_eq!(rx.recv().await, Some(2));
+    assert_eq!(rx.recv().await, None);
+}
+
+#[tokio::test]
+async fn shutdown_flushes_log() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    use std::fs::read_to_string;
+    use tempfile::NamedTempFile;
+    let path = NamedTempFile::new()?;
+    let test_args = Args {
+        channel_name: String::from("&"),
+        from_stdin: true,
+        log_file: Some(path.as_ref().to_path_buf()),
+        flush_interval: 3600,
+        ..Default::default()
+    };
+
+    // Stdin that stays open well past the shutdown, like an idle terminal
+    struct Idle(io::Cursor<Vec<u8>>, std::time::Instant);
+    impl Read for Idle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            while self.1.elapsed() < Duration::from_secs(1) {
+                match self.0.read(buf)? {
+                    0 => std::thread::sleep(Duration::from_millis(10)),
+                    n => return Ok(n),
+                }
+            }
+            Ok(0)
+        }
+    }
+    let input = format!("{PRIVMSG_EXAMPLE}\n").into_bytes();
+    let input = Idle(io::Cursor::new(input), std::time::Instant::now());
+
+    let shutdown = tokio::time::sleep(Duration::from_millis(200));
+    init_until(test_args, input, io::sink(), None, shutdown).await?;
+
+    let logged = read_to_string(path)?;
+    assert_eq!(logged.lines().count(), 1, "{logged}");
+    assert!(
+        logged.ends_with("PRIVMSG #bread :bread bread bread\n"),
+        "{logged}"
+    );
+    Ok(())
 }
 
 #[tokio::test]
-async fn receiver_splitter_is_balanced() {
-    let (tx, rx) = mpsc::unbounded_channel();
-    let (handle, mut out1, mut out2) = receiver_splitter(rx);
-    let test_msg = "Hewwo, I am a string";
+async fn room_state_is_shared() {
+    use twitch_irc::message::IRCMessage;
+    let raw = "@room-id=910;emote-only=1 :tmi.twitch.tv ROOMSTATE #bread";
+    let message = ServerMessage::try_from(IRCMessage::parse(raw).unwrap()).unwrap();
 
-    tx.send(test_msg).unwrap();
-    let res1 = out1.recv().await.unwrap();
-    let res2 = out2.recv().await.unwrap();
+    use crate::sink::run_sink;
+    let (tx, rx) = queue::channel(16, Policy::Block);
+    let (mut sink, mut room_state) = TerminalSink::new(io::sink(), chrono::Utc::now());
+    let handle = tokio::spawn(async move { run_sink(rx, &mut sink).await });
+    assert!(!room_state.borrow().emote_only);
 
-    assert_eq!(test_msg, res1);
-    assert_eq!(test_msg, res2);
+    tx.send(message).await.unwrap();
+    room_state.changed().await.unwrap();
+    assert!(room_state.borrow().emote_only);
 
     drop(tx);
-    handle.await.unwrap();
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn session_counts_seen_and_logged() {
+    use tempfile::NamedTempFile;
+    let path = NamedTempFile::new().expect("Could not get temp path");
+    let test_args = Args {
+        channel_name: String::from("&"),
+        from_stdin: true,
+        log_file: Some(path.as_ref().to_path_buf()),
+        ..Default::default()
+    };
+    let input = format!("{PRIVMSG_EXAMPLE}\n{PONG_MSG_EXAMPLE}\n{PRIVMSG_EXAMPLE}\n");
+    let counts = run_session(test_args, io::Cursor::new(input), io::sink(), None, async {
+        std::future::pending().await
+    })
+    .await
+    .unwrap();
+    assert_eq!(counts, (2, Some(2)));
+}
+
+#[test]
+fn display_session_summary() {
+    let summary = SessionSummary {
+        duration: chrono::Duration::seconds(3725),
+        messages_seen: 120,
+        messages_logged: Some(118),
+    };
+    assert_eq!(
+        summary.to_string(),
+        "Session lasted 01:02:05, 120 messages seen, 118 logged"
+    );
+    let summary = SessionSummary {
+        messages_logged: None,
+        ..summary
+    };
+    assert_eq!(
+        summary.to_string(),
+        "Session lasted 01:02:05, 120 messages seen"
+    );
+}
+
+#[tokio::test]
+async fn slow_display_reports_dropped() {
+    let (tx, rx) = queue::channel(2, Policy::DropOldest);
+    for _ in 0..5 {
+        tx.send(ServerMessage::Privmsg(make_privmsg_example()))
+            .await
+            .unwrap();
+    }
+    drop(tx);
+
+    struct WriteLockBuf(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+    impl Write for WriteLockBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    let output = std::sync::Arc::default();
+    let out = WriteLockBuf(std::sync::Arc::clone(&output));
+    let (mut sink, _) = TerminalSink::new(out, chrono::Utc::now());
+    let seen = crate::sink::run_sink(rx, &mut sink).await.unwrap();
+    assert_eq!(seen, 2);
+
+    let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+    assert!(output.contains("3 messages dropped"), "{output}");
+}
+
+#[tokio::test]
+async fn summary_counts_are_found_by_output() {
+    struct WriteLockBuf(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+    impl Write for WriteLockBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let path = tempfile::NamedTempFile::new().unwrap();
+    let test_args = Args {
+        channel_name: String::from("&"),
+        log_file: Some(path.path().to_path_buf()),
+        overlay: Some("127.0.0.1:0".to_string()),
+        ..Default::default()
+    };
+    let (tx, rx) = queue::channel(16, Policy::Block);
+    for _ in 0..3 {
+        tx.send(ServerMessage::Privmsg(make_privmsg_example()))
+            .await
+            .unwrap();
+    }
+    drop(tx);
+    let output = std::sync::Arc::default();
+    let out = WriteLockBuf(std::sync::Arc::clone(&output));
+    let counts = init_with_input(test_args, rx, out, None, Some(RecentMessages::default()))
+        .await
+        .unwrap();
+    assert_eq!(counts, (3, Some(3)));
+
+    // Written to the output given, not the process stdout
+    let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert!(lines[0].starts_with("Logging started at "), "{output}");
+    assert!(
+        lines[1].starts_with("Overlay events at ws://127.0.0.1:"),
+        "{output}"
+    );
 }
@@ -0,0 +1,294 @@
+//! Outputs for chat messages, & fanning messages out to them
+//!
+//! The terminal & the log file are both a [Sink]. New outputs only need to
+//! implement [Sink] & be added to a [Broadcaster].
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use twitch_irc::message::ServerMessage;
+
+use crate::error::Result;
+use crate::queue::{self, Policy, Receiver};
+
+/// Somewhere chat messages go.
+#[async_trait]
+pub trait Sink: Send + 'static {
+    /// Handles one message.
+    ///
+    /// Returning `Ok(false)` stops this sink, & with it the [Broadcaster],
+    /// e.g. when the terminal was closed.
+    async fn send(&mut self, message: ServerMessage) -> Result<bool>;
+
+    /// `count` messages were dropped before reaching this sink, see
+    /// [Policy::DropOldest].
+    async fn dropped(&mut self, _count: u64) -> Result<()> {
+        Ok(())
+    }
+
+    /// How often [Sink::tick] should be called, if at all.
+    fn tick_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Periodic work, like flushing a file.
+    async fn tick(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once after the last message, even after an error.
+    ///
+    /// Returns how many messages the sink output, for the session summary.
+    async fn finish(&mut self) -> Result<u64>;
+}
+
+/// Feeds `incoming` to `sink` until the queue closes or the sink stops.
+pub async fn run_sink<S: Sink + ?Sized>(
+    mut incoming: Receiver<ServerMessage>,
+    sink: &mut S,
+) -> Result<u64> {
+    let mut interval = sink.tick_interval().map(|period| {
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval
+    });
+    let res = async {
+        loop {
+            tokio::select! {
+                message = incoming.recv() => match message {
+                    Some(message) => {
+                        let dropped = incoming.take_dropped();
+                        if dropped > 0 {
+                            sink.dropped(dropped).await?;
+                        }
+                        if !sink.send(message).await? {
+                            return Ok(());
+                        }
+                    }
+                    None => return Ok(()),
+                },
+                _ = next_tick(&mut interval) => sink.tick().await?,
+            }
+        }
+    }
+    .await;
+    let finished = sink.finish().await;
+    res.and(finished)
+}
+
+/// Next tick of `interval`, never completes without one.
+async fn next_tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Lets sinks picked at runtime be used like any other.
+#[async_trait]
+impl<S: Sink + ?Sized> Sink for Box<S> {
+    async fn send(&mut self, message: ServerMessage) -> Result<bool> {
+        (**self).send(message).await
+    }
+    async fn dropped(&mut self, count: u64) -> Result<()> {
+        (**self).dropped(count).await
+    }
+    fn tick_interval(&self) -> Option<Duration> {
+        (**self).tick_interval()
+    }
+    async fn tick(&mut self) -> Result<()> {
+        (**self).tick().await
+    }
+    async fn finish(&mut self) -> Result<u64> {
+        (**self).finish().await
+    }
+}
+
+/// Fans every message out to any number of [Sink]s.
+///
+/// Each sink runs in its own task behind its own queue, so a slow sink only
+/// holds up the others if its [Policy] is [Policy::Block].
+pub struct Broadcaster {
+    capacity: usize,
+    sinks: Vec<(Box<dyn Sink>, Policy)>,
+}
+
+impl Broadcaster {
+    /// `capacity` is the size of each sink's queue.
+    pub fn new(capacity: usize) -> Broadcaster {
+        Broadcaster {
+            capacity,
+            sinks: vec![],
+        }
+    }
+
+    /// Adds a sink, its queue is full according to `policy`.
+    pub fn add_sink<S: Sink>(&mut self, sink: S, policy: Policy) -> &mut Broadcaster {
+        self.sinks.push((Box::new(sink), policy));
+        self
+    }
+
+    /// How many sinks were added, the index the next one's result will have
+    /// in [Broadcaster::run].
+    pub fn sink_count(&self) -> usize {
+        self.sinks.len()
+    }
+
+    /// Sends `incoming` to every sink until it closes or any sink stops.
+    ///
+    /// Returns the result of [Sink::finish] for every sink, in the order they
+    /// were added.
+    pub async fn run(self, mut incoming: Receiver<ServerMessage>) -> Vec<Result<u64>> {
+        let stopped = Arc::new(Notify::new());
+        let mut outputs = vec![];
+        let mut tasks: Vec<JoinHandle<Result<u64>>> = vec![];
+        for (mut sink, policy) in self.sinks {
+            let (tx, rx) = queue::channel(self.capacity, policy);
+            let stopped = Arc::clone(&stopped);
+            outputs.push(tx);
+            tasks.push(tokio::spawn(async move {
+                let res = run_sink(rx, sink.as_mut()).await;
+                // A sink can stop early, e.g. on a fatal NOTICE. Don't wait
+                // for the next message, that could be minutes away.
+                stopped.notify_one();
+                res
+            }));
+        }
+
+        'splitter: loop {
+            let message = tokio::select! {
+                message = incoming.recv() => match message {
+                    Some(message) => message,
+                    None => break,
+                },
+                _ = stopped.notified() => break,
+            };
+            for output in &outputs {
+                if output.send(message.clone()).await.is_err() {
+                    break 'splitter;
+                }
+            }
+        }
+        // Closes every queue, the sinks finish what is left
+        drop(outputs);
+
+        let mut results = vec![];
+        for task in tasks {
+            results.push(task.await.unwrap());
+        }
+        results
+    }
+}
+
+/// Collects the irc of every message
+#[cfg(test)]
+struct Collect(Arc<std::sync::Mutex<Vec<String>>>);
+
+#[cfg(test)]
+#[async_trait]
+impl Sink for Collect {
+    async fn send(&mut self, message: ServerMessage) -> Result<bool> {
+        use twitch_irc::message::AsRawIRC;
+        self.0.lock().unwrap().push(message.source().as_raw_irc());
+        Ok(true)
+    }
+    async fn finish(&mut self) -> Result<u64> {
+        Ok(self.0.lock().unwrap().len() as u64)
+    }
+}
+
+/// Fails on the first message
+#[cfg(test)]
+struct Fails;
+
+#[cfg(test)]
+#[async_trait]
+impl Sink for Fails {
+    async fn send(&mut self, _: ServerMessage) -> Result<bool> {
+        Err(crate::error::Error::Notice("bread".to_string()))
+    }
+    async fn finish(&mut self) -> Result<u64> {
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+fn privmsg() -> ServerMessage {
+    ServerMessage::Privmsg(crate::setup::make_privmsg_example())
+}
+
+#[tokio::test]
+async fn broadcaster_is_balanced() {
+    let (tx, rx) = queue::channel(16, Policy::Block);
+    let outputs: Vec<Arc<std::sync::Mutex<Vec<String>>>> = (0..3).map(|_| Arc::default()).collect();
+    let mut broadcaster = Broadcaster::new(16);
+    for output in &outputs {
+        broadcaster.add_sink(Collect(Arc::clone(output)), Policy::Block);
+    }
+
+    tx.send(privmsg()).await.unwrap();
+    tx.send(privmsg()).await.unwrap();
+    drop(tx);
+    let results = broadcaster.run(rx).await;
+
+    assert_eq!(results.len(), 3);
+    for res in results {
+        assert_eq!(res.unwrap(), 2);
+    }
+    let first = outputs[0].lock().unwrap().clone();
+    for output in &outputs {
+        assert_eq!(*output.lock().unwrap(), first);
+    }
+}
+
+#[tokio::test]
+async fn failing_sink_stops_the_others() {
+    let (tx, rx) = queue::channel(16, Policy::Block);
+    let collected = Arc::default();
+    let mut broadcaster = Broadcaster::new(16);
+    broadcaster
+        .add_sink(Fails, Policy::Block)
+        .add_sink(Collect(Arc::clone(&collected)), Policy::Block);
+
+    // The input stays open, the failure alone has to end the run
+    tx.send(privmsg()).await.unwrap();
+    let results = tokio::time::timeout(Duration::from_secs(5), broadcaster.run(rx))
+        .await
+        .expect("A failed sink should stop the broadcaster");
+    assert!(matches!(results[0], Err(crate::error::Error::Notice(_))));
+    assert_eq!(results[1].as_ref().unwrap(), &1);
+    drop(tx);
+}
+
+#[tokio::test]
+async fn sink_is_ticked() {
+    struct Ticks(u64);
+
+    #[async_trait]
+    impl Sink for Ticks {
+        async fn send(&mut self, _: ServerMessage) -> Result<bool> {
+            Ok(true)
+        }
+        fn tick_interval(&self) -> Option<Duration> {
+            Some(Duration::from_millis(10))
+        }
+        async fn tick(&mut self) -> Result<()> {
+            self.0 += 1;
+            Ok(())
+        }
+        async fn finish(&mut self) -> Result<u64> {
+            Ok(self.0)
+        }
+    }
+
+    let (tx, rx) = queue::channel(16, Policy::Block);
+    let closer = rx.closer();
+    let run = tokio::spawn(async move { run_sink(rx, &mut Ticks(0)).await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    closer.close();
+    assert!(run.await.unwrap().unwrap() > 0);
+    drop(tx);
+}
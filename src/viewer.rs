@@ -0,0 +1,242 @@
+//! Builder for embedding the viewer pipeline in another program
+//!
+//! A [Viewer] reads messages from a [Source], drops the ones a filter
+//! rejects, & hands the rest to every [Sink].
+use std::io::Read;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+use twitch_irc::message::ServerMessage;
+
+use crate::args::{DEFAULT_BUFFER_SIZE, DEFAULT_JOIN_TIMEOUT};
+use crate::endpoint::Pool;
+use crate::error::{Error, Result};
+use crate::queue::{self, Policy, Receiver};
+use crate::setup::{bounded_input, build_irc_client, filein_channel_task_create, wait_for_join};
+use crate::sink::{Broadcaster, Sink};
+
+/// Where a [Viewer] gets its messages.
+pub enum Source {
+    /// Joins a channel anonymously, until the connection ends.
+    ///
+    /// Fails with [Error::JoinTimeout] if the join isn't confirmed in time,
+    /// see [ViewerBuilder::join_timeout].
+    Channel(String),
+    /// Raw irc, one message per line, like a log written by
+    /// [LogSink](crate::logging::LogSink).
+    Irc(Box<dyn Read + Send>),
+    /// Messages from a client you already have, e.g. the one of your bot.
+    Client(UnboundedReceiver<ServerMessage>),
+}
+
+type Filter = Box<dyn Fn(&ServerMessage) -> bool + Send>;
+
+/// Configures a [Viewer], see [Viewer::builder].
+pub struct ViewerBuilder {
+    source: Source,
+    filters: Vec<Filter>,
+    sinks: Vec<(Box<dyn Sink>, Policy)>,
+    buffer_size: usize,
+    pool: Pool,
+    join_timeout: Option<Duration>,
+}
+
+impl ViewerBuilder {
+    /// Only messages every filter returns `true` for reach the sinks.
+    pub fn filter<F>(mut self, filter: F) -> ViewerBuilder
+    where
+        F: Fn(&ServerMessage) -> bool + Send + 'static,
+    {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Adds an output, `policy` decides what happens when it falls behind.
+    pub fn sink<S: Sink>(mut self, sink: S, policy: Policy) -> ViewerBuilder {
+        self.sinks.push((Box::new(sink), policy));
+        self
+    }
+
+    /// Messages each queue holds, see [DEFAULT_BUFFER_SIZE].
+    ///
+    /// # Panics
+    /// If `buffer_size` is 0.
+    pub fn buffer_size(mut self, buffer_size: usize) -> ViewerBuilder {
+        assert!(buffer_size > 0, "A queue must be able to hold a message");
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// How a [Source::Channel] connects.
+    pub fn pool(mut self, pool: Pool) -> ViewerBuilder {
+        self.pool = pool;
+        self
+    }
+
+    /// How long a [Source::Channel] waits for twitch to confirm the join,
+    /// [DEFAULT_JOIN_TIMEOUT] by default. `None` waits as long as it takes.
+    pub fn join_timeout(mut self, join_timeout: Option<Duration>) -> ViewerBuilder {
+        self.join_timeout = join_timeout;
+        self
+    }
+
+    pub fn build(self) -> Viewer {
+        Viewer { config: self }
+    }
+}
+
+/// A configured pipeline from a [Source] to any number of [Sink]s.
+pub struct Viewer {
+    config: ViewerBuilder,
+}
+
+impl Viewer {
+    pub fn builder(source: Source) -> ViewerBuilder {
+        ViewerBuilder {
+            source,
+            filters: vec![],
+            sinks: vec![],
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            pool: Pool::default(),
+            join_timeout: Some(DEFAULT_JOIN_TIMEOUT),
+        }
+    }
+
+    /// Runs until the source ends or any sink stops.
+    ///
+    /// Returns the result of [Sink::finish] for every sink, in the order they
+    /// were added. Errors of the source itself, like an invalid channel name
+    /// or a line that isn't irc, are returned on their own.
+    pub async fn run(self) -> Result<Vec<Result<u64>>> {
+        let ViewerBuilder {
+            source,
+            filters,
+            sinks,
+            buffer_size,
+            pool,
+            join_timeout,
+        } = self.config;
+
+        // Keeps the connection open while the sinks run
+        let mut client = None;
+        let mut reader = None;
+        let incoming = match source {
+            Source::Channel(channel) => {
+                let (incoming, irc_client) = build_irc_client(None, &pool);
+                irc_client
+                    .join(channel.clone())
+                    .map_err(|err| Error::InvalidChannel(err.to_string()))?;
+                client = Some((irc_client, channel));
+                bounded_input(incoming, buffer_size)
+            }
+            Source::Irc(input) => {
+                let (handle, incoming) = filein_channel_task_create(input, buffer_size);
+                reader = Some(handle);
+                incoming
+            }
+            Source::Client(incoming) => bounded_input(incoming, buffer_size),
+        };
+        let incoming = filtered(incoming, filters, buffer_size);
+
+        let mut broadcaster = Broadcaster::new(buffer_size);
+        for (sink, policy) in sinks {
+            broadcaster.add_sink(sink, policy);
+        }
+        let run = broadcaster.run(incoming);
+        let results = match (&client, join_timeout) {
+            (Some((client, channel)), Some(timeout)) => {
+                tokio::pin!(run);
+                let joined = wait_for_join(channel, timeout, || {
+                    let (client, channel) = (client, channel.clone());
+                    async move { client.get_channel_status(channel).await.1 }
+                });
+                tokio::select! {
+                    results = &mut run => results,
+                    res = joined => {
+                        res?;
+                        run.await
+                    }
+                }
+            }
+            _ => run.await,
+        };
+        drop(client);
+        if let Some(reader) = reader {
+            reader.await.unwrap()?;
+        }
+        Ok(results)
+    }
+}
+
+/// Passes on the messages every filter accepts.
+fn filtered(
+    mut incoming: Receiver<ServerMessage>,
+    filters: Vec<Filter>,
+    capacity: usize,
+) -> Receiver<ServerMessage> {
+    if filters.is_empty() {
+        return incoming;
+    }
+    let (tx, rx) = queue::channel(capacity, Policy::Block);
+    tokio::spawn(async move {
+        while let Some(message) = incoming.recv().await {
+            if filters.iter().all(|filter| filter(&message)) && tx.send(message).await.is_err() {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+#[tokio::test]
+async fn filters_apply_to_every_sink() {
+    use crate::logging::{FlushPolicy, LogSink};
+    use crate::setup::PRIVMSG_EXAMPLE;
+
+    let room_state = "@room-id=910;emote-only=1 :tmi.twitch.tv ROOMSTATE #bread";
+    let input = format!("{PRIVMSG_EXAMPLE}\n{room_state}\n{PRIVMSG_EXAMPLE}\n");
+    let results = Viewer::builder(Source::Irc(Box::new(std::io::Cursor::new(input))))
+        .filter(|message| !matches!(message, ServerMessage::Privmsg(_)))
+        .sink(
+            LogSink::new(std::io::sink(), FlushPolicy::EveryMessage),
+            Policy::Block,
+        )
+        .sink(
+            LogSink::new(std::io::sink(), FlushPolicy::EveryMessage),
+            Policy::Block,
+        )
+        .buffer_size(1)
+        .build()
+        .run()
+        .await
+        .unwrap();
+    let logged: Vec<u64> = results.into_iter().map(|res| res.unwrap()).collect();
+    assert_eq!(logged, [1, 1]);
+}
+
+#[tokio::test]
+async fn source_errors_are_returned() {
+    let input = std::io::Cursor::new("@tags-but-no-command\n");
+    let res = Viewer::builder(Source::Irc(Box::new(input)))
+        .build()
+        .run()
+        .await;
+    assert!(matches!(res, Err(Error::Parse(_))));
+}
+
+#[tokio::test]
+async fn unconfirmed_join_times_out() {
+    use crate::server::Server;
+
+    let server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .confirm_joins(false);
+    let _endpoint = crate::endpoint::test_endpoint(server.endpoint().unwrap()).await;
+    let _playing = server.play(|_| vec![]);
+    let res = Viewer::builder(Source::Channel("bread".to_string()))
+        .join_timeout(Some(Duration::from_millis(500)))
+        .build()
+        .run()
+        .await;
+    assert!(matches!(res, Err(Error::JoinTimeout(_))));
+}
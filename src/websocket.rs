@@ -0,0 +1,59 @@
+//! IRC over WebSocket, as a plain stream of lines
+//!
+//! Twitch sends one or more irc lines per text message & expects one line
+//! per message back. Bridging that to a byte stream lets the rest of the
+//! crate treat a WebSocket like any TCP connection.
+use async_tungstenite::tungstenite::Message;
+use async_tungstenite::WebSocketStream;
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream};
+
+/// Bytes buffered in each direction of the bridge.
+const BRIDGE_CAPACITY: usize = 64 * 1024;
+
+/// A stream of irc lines carried by `ws`.
+///
+/// The WebSocket is closed once the stream is dropped, the stream ends once
+/// the WebSocket does.
+pub fn into_stream<S>(ws: WebSocketStream<S>) -> DuplexStream
+where
+    S: futures_util::AsyncRead + futures_util::AsyncWrite + Send + Unpin + 'static,
+{
+    let (ours, theirs) = tokio::io::duplex(BRIDGE_CAPACITY);
+    tokio::spawn(bridge(ws, theirs));
+    ours
+}
+
+async fn bridge<S, T>(ws: WebSocketStream<S>, stream: T)
+where
+    S: futures_util::AsyncRead + futures_util::AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite,
+{
+    let (mut ws_out, mut ws_in) = ws.split();
+    let (read, mut write) = tokio::io::split(stream);
+    let mut lines = BufReader::new(read).lines();
+    loop {
+        tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => {
+                    if ws_out.send(Message::Text(line)).await.is_err() {
+                        break;
+                    }
+                }
+                _ => break,
+            },
+            message = ws_in.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let text: String = text.lines().map(|line| format!("{line}\r\n")).collect();
+                    if write.write_all(text.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+                // Pings are answered by the WebSocket itself
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Binary(_) | Message::Frame(_))) => (),
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+            },
+        }
+    }
+    let _ = ws_out.close().await;
+}
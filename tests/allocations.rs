@@ -0,0 +1,62 @@
+//! Chat lines & badges are formatted without allocating
+//!
+//! A test binary of its own, since it needs its own global allocator.
+use chrono::Utc;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::{self, Write};
+use twitch_irc::message::{IRCMessage, ServerMessage};
+
+use twitch_ircv::{parse_badges, Formatter};
+
+/// Counts the allocations of each thread, so the test harness doesn't add to
+/// them.
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn allocations_in(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn chat_lines_are_written_without_allocating() {
+    let chat: Vec<_> = include_str!("irc_data_no_ping")
+        .lines()
+        .filter_map(|line| {
+            match ServerMessage::try_from(IRCMessage::parse(line).unwrap()).unwrap() {
+                ServerMessage::Privmsg(msg) => Some(msg),
+                _ => None,
+            }
+        })
+        .collect();
+    assert!(!chat.is_empty());
+    let formatter = Formatter::new(Utc::now());
+
+    let allocations = allocations_in(|| {
+        for msg in &chat {
+            write!(io::sink(), "{}", parse_badges(&msg.badges)).unwrap();
+            writeln!(io::sink(), "{}", formatter.chat_line(msg)).unwrap();
+            writeln!(io::sink(), "{}", formatter.wrapped(40).chat_line(msg)).unwrap();
+        }
+    });
+    assert_eq!(allocations, 0);
+}
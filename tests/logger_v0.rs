@@ -6,17 +6,18 @@ use std::io::Cursor;
 
 use twitch_irc::message::{IRCMessage, ServerMessage};
 
-use twitch_ircv::logging::log_v0;
+use twitch_ircv::log_v0;
 
 fn valid_irc(s: &str) -> bool {
     s.contains("PRIVMSG")
         || s.contains("USERNOTICE")
         || s.contains("CLEARCHAT")
         || s.contains("CLEARMSG")
+        || s.contains("ROOMSTATE")
 }
 
-#[tokio::test]
-async fn test_no_ping() -> Result<(), Box<dyn Error>> {
+#[test]
+fn test_no_ping() -> Result<(), Box<dyn Error>> {
     let f = File::open("tests/irc_data_no_ping")?;
     let f = BufReader::new(f);
     let irc_lines: Vec<String> = f.lines().map(|s| s.unwrap()).collect();
@@ -31,7 +32,7 @@ async fn test_no_ping() -> Result<(), Box<dyn Error>> {
     for line in irc_lines {
         let msg = IRCMessage::parse(&line)?;
         let msg = ServerMessage::try_from(msg)?;
-        log_v0(msg, &mut buff).await;
+        log_v0(msg, &mut buff)?;
     }
 
     buff.set_position(0);